@@ -1,8 +1,10 @@
 #![no_main]
 use libfuzzer_sys::fuzz_target;
 use parallel_downloader::http::send_head_request;
+use parallel_downloader::DownloadConfig;
 use native_tls::{TlsConnector};
 use std::net::TcpStream;
+use url::Url;
 
 fuzz_target!(|data: &[u8]| {
     if let Ok(path) = std::str::from_utf8(data) {
@@ -10,7 +12,10 @@ fuzz_target!(|data: &[u8]| {
         if let Ok(stream) = TcpStream::connect("93.184.216.34:443") {
             let connector = TlsConnector::new().unwrap();
             if let Ok(mut tls_stream) = connector.connect(hostname, stream) {
-                let _ = send_head_request(&mut tls_stream, hostname, path);
+                if let Ok(url) = Url::parse("https://example.com").and_then(|base| base.join(path)) {
+                    let config = DownloadConfig::new(url.to_string(), String::new(), 1);
+                    let _ = send_head_request(&mut tls_stream, &url, &config);
+                }
             }
         }
     }
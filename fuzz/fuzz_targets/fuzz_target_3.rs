@@ -14,16 +14,16 @@ fuzz_target!(|data: &[u8]| {
 
     let manager = DownloadManager::new(config, 1000);
 
-    for i in 0..4 {
-        let filename = format!("part{}.jpg", i);
+    for i in 0..manager.num_parts() {
+        let filename = manager.get_part_filename(i);
         let mut part_file = File::create(&filename).unwrap();
         part_file.write_all(data).unwrap();
     }
 
     let _ = manager.merge_parts();
 
-    for i in 0..4 {
-        let filename = format!("part{}.jpg", i);
+    for i in 0..manager.num_parts() {
+        let filename = manager.get_part_filename(i);
         let _ = std::fs::remove_file(&filename);
     }
 });
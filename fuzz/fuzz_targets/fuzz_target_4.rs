@@ -2,14 +2,16 @@
 use libfuzzer_sys::fuzz_target;
 use parallel_downloader::downloader::download_part;
 use parallel_downloader::dns::get_request_ip;
-use parallel_downloader::connection::establish_tls_connection;
+use parallel_downloader::connection::{establish_tls_connection, TlsOptions};
+use parallel_downloader::tcp::Timeouts;
 use parallel_downloader::config::DownloadConfig;
+use url::Url;
 
 fuzz_target!(|data: &[u8]| {
     if let Ok(path) = std::str::from_utf8(data) {
         let hostname = "example.com";
         if let Ok(ip) = get_request_ip(hostname) {
-            if let Ok(_stream) = establish_tls_connection(hostname, ip) {
+            if let Ok(_stream) = establish_tls_connection(hostname, ip, 443, &TlsOptions::default(), &Timeouts::default()) {
                 let part = parallel_downloader::downloader::DownloadPart {
                     start: 0,
                     end: 100,
@@ -21,7 +23,9 @@ fuzz_target!(|data: &[u8]| {
                     4,
                 );
 
-                let _ = download_part(hostname, path, &part, &config);
+                if let Ok(url) = Url::parse("https://example.com").and_then(|base| base.join(path)) {
+                    let _ = download_part(&url, &part, &config, &mut std::io::sink());
+                }
             }
         }
     }
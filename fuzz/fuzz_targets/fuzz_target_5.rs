@@ -0,0 +1,14 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use parallel_downloader::response::{parse_content_range, read_response_head, Response};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(response) = Response::parse(data) {
+        let _ = response.content_length();
+        let _ = response.content_range();
+    }
+    let _ = read_response_head(&mut &data[..]);
+    if let Ok(value) = std::str::from_utf8(data) {
+        let _ = parse_content_range(value);
+    }
+});
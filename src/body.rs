@@ -0,0 +1,304 @@
+//! # Response Body Decoding
+//!
+//! This module turns the raw bytes that follow a response head into the body of the
+//! resource. It removes the transfer framing (`Transfer-Encoding: chunked`, a
+//! `Content-Length`, or the end of the connection) and, when asked to, decodes
+//! `Content-Encoding: gzip` and `deflate`.
+//!
+//! ## Features
+//! - Decodes chunked transfer-encoding, including chunk extensions and trailers.
+//! - Limits the body to `Content-Length` when no other framing is used.
+//! - Transparently decompresses gzip and deflate content.
+
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use crate::error::DownloaderError;
+use crate::response::{Headers, Response};
+
+/// Longest chunk-size or trailer line that will be accepted.
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+
+/// Value of `Accept-Encoding` for requests whose body is written as-is, e.g. byte ranges.
+pub const IDENTITY_ENCODING: &str = "identity";
+
+/// Value of `Accept-Encoding` for requests whose body is decoded by [`content_reader`].
+pub const ACCEPTED_ENCODINGS: &str = "gzip, deflate";
+
+/// Returns a reader over the body of `response`, with the transfer framing removed.
+///
+/// # Parameters
+/// - `response`: The parsed response head.
+/// - `prefix`: Body bytes that were read together with the head.
+/// - `stream`: The connection the rest of the body is read from.
+///
+/// # Errors
+/// Returns a `DownloaderError` for transfer codings other than `chunked`.
+pub fn transfer_reader<'a, R: Read + 'a>(
+    response: &Response,
+    prefix: Vec<u8>,
+    stream: R,
+) -> Result<Box<dyn Read + 'a>, DownloaderError> {
+    let raw = Cursor::new(prefix).chain(stream);
+
+    let codings: Vec<String> = response
+        .headers
+        .get_all("transfer-encoding")
+        .flat_map(|value| value.split(','))
+        .map(|coding| coding.trim().to_ascii_lowercase())
+        .filter(|coding| !coding.is_empty() && coding != "identity")
+        .collect();
+
+    match codings.as_slice() {
+        [] => match response.content_length()? {
+            Some(length) => Ok(Box::new(raw.take(length))),
+            None => Ok(Box::new(raw)),
+        },
+        [coding] if coding == "chunked" => Ok(Box::new(ChunkedReader::new(BufReader::new(raw)))),
+        _ => Err(DownloaderError::ResponseError(format!(
+            "Unsupported Transfer-Encoding: {}",
+            codings.join(", ")
+        ))),
+    }
+}
+
+/// Wraps `body` so that it yields the decoded content of `response`.
+///
+/// # Errors
+/// Returns a `DownloaderError` for content codings other than `gzip` and `deflate`.
+pub fn content_reader<'a>(
+    response: &Response,
+    body: Box<dyn Read + 'a>,
+) -> Result<Box<dyn Read + 'a>, DownloaderError> {
+    let codings: Vec<String> = response
+        .headers
+        .get_all("content-encoding")
+        .flat_map(|value| value.split(','))
+        .map(|coding| coding.trim().to_ascii_lowercase())
+        .filter(|coding| !coding.is_empty() && coding != "identity")
+        .collect();
+
+    // Codings are listed in the order they were applied, so undo them in reverse.
+    codings.iter().rev().try_fold(body, |reader, coding| match coding.as_str() {
+        "gzip" | "x-gzip" => Ok(Box::new(GzDecoder::new(reader)) as Box<dyn Read>),
+        "deflate" => Ok(Box::new(ZlibDecoder::new(reader)) as Box<dyn Read>),
+        other => Err(DownloaderError::ResponseError(format!(
+            "Unsupported Content-Encoding: {}",
+            other
+        ))),
+    })
+}
+
+/// Whether `response` carries a content coding that `content_reader` would undo.
+pub fn is_content_encoded(response: &Response) -> bool {
+    response
+        .headers
+        .get_all("content-encoding")
+        .flat_map(|value| value.split(','))
+        .any(|coding| !coding.trim().is_empty() && !coding.trim().eq_ignore_ascii_case("identity"))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ChunkState {
+    /// Expecting a chunk-size line.
+    Size,
+    /// Inside the data of a chunk.
+    Data,
+    /// Expecting the line break that ends a chunk's data.
+    DataEnd,
+    /// The last chunk and the trailers have been read.
+    Done,
+}
+
+/// Decodes a body sent with `Transfer-Encoding: chunked`.
+pub struct ChunkedReader<R> {
+    inner: R,
+    state: ChunkState,
+    remaining: u64,
+    trailers: Headers,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    /// Creates a reader that decodes the chunked body read from `inner`.
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            state: ChunkState::Size,
+            remaining: 0,
+            trailers: Headers::default(),
+        }
+    }
+
+    /// Trailer fields sent after the last chunk; empty until the body has been read.
+    pub fn trailers(&self) -> &Headers {
+        &self.trailers
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = Vec::new();
+        (&mut self.inner).take(MAX_LINE_LENGTH).read_until(b'\n', &mut line)?;
+        if line.last() != Some(&b'\n') {
+            return Err(if line.len() as u64 == MAX_LINE_LENGTH {
+                invalid_chunk("chunk line too long")
+            } else {
+                truncated_chunk()
+            });
+        }
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        String::from_utf8(line).map_err(|_| invalid_chunk("chunk line is not valid UTF-8"))
+    }
+
+    fn read_trailers(&mut self) -> io::Result<()> {
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                return Ok(());
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid_chunk("malformed trailer field"))?;
+            self.trailers.push(name.trim(), value.trim());
+        }
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.state {
+                ChunkState::Size => {
+                    let line = self.read_line()?;
+                    let size = line.split(';').next().unwrap_or_default().trim();
+                    if size.is_empty() || size.len() > 16 {
+                        return Err(invalid_chunk("invalid chunk size"));
+                    }
+                    let size = u64::from_str_radix(size, 16)
+                        .map_err(|_| invalid_chunk("invalid chunk size"))?;
+                    if size == 0 {
+                        self.read_trailers()?;
+                        self.state = ChunkState::Done;
+                    } else {
+                        self.remaining = size;
+                        self.state = ChunkState::Data;
+                    }
+                }
+                ChunkState::Data => {
+                    if buf.is_empty() {
+                        return Ok(0);
+                    }
+                    let max = buf.len().min(self.remaining.min(usize::MAX as u64) as usize);
+                    let n = self.inner.read(&mut buf[..max])?;
+                    if n == 0 {
+                        return Err(truncated_chunk());
+                    }
+                    self.remaining -= n as u64;
+                    if self.remaining == 0 {
+                        self.state = ChunkState::DataEnd;
+                    }
+                    return Ok(n);
+                }
+                ChunkState::DataEnd => {
+                    if !self.read_line()?.is_empty() {
+                        return Err(invalid_chunk("missing line break after chunk data"));
+                    }
+                    self.state = ChunkState::Size;
+                }
+                ChunkState::Done => return Ok(0),
+            }
+        }
+    }
+}
+
+fn invalid_chunk(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid chunked body: {}", message))
+}
+
+fn truncated_chunk() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed inside chunked body")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{content_reader, transfer_reader, ChunkedReader};
+    use crate::response::Response;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use std::io::{self, Read, Write};
+
+    fn response(head: &str) -> Response {
+        Response::parse(head.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_chunked_body_with_extensions_and_trailers() {
+        let raw: &[u8] = b"5;name=value\r\nhello\r\n7\r\n, world\r\n0\r\nChecksum: abc\r\n\r\nGARBAGE";
+        let mut reader = ChunkedReader::new(raw);
+        let mut body = String::new();
+        reader.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "hello, world");
+        assert_eq!(reader.trailers().get("checksum"), Some("abc"));
+    }
+
+    #[test]
+    fn test_truncated_chunked_body() {
+        let mut reader = ChunkedReader::new(&b"a\r\nshort"[..]);
+        let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut reader = ChunkedReader::new(&b"zz\r\n"[..]);
+        let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_transfer_reader_framing() {
+        let chunked = response("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n");
+        let mut body = String::new();
+        transfer_reader(&chunked, b"3\r\nabc\r\n".to_vec(), &b"0\r\n\r\n"[..])
+            .unwrap()
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "abc");
+
+        let sized = response("HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\n");
+        let mut body = String::new();
+        transfer_reader(&sized, b"ab".to_vec(), &b"cdEXTRA"[..])
+            .unwrap()
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "abcd");
+
+        let unknown = response("HTTP/1.1 200 OK\r\nTransfer-Encoding: compress\r\n\r\n");
+        assert!(transfer_reader(&unknown, Vec::new(), io::empty()).is_err());
+    }
+
+    #[test]
+    fn test_content_decoding() {
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(b"compressed text").unwrap();
+        let gzip = gzip.finish().unwrap();
+        let gzipped = response("HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\n\r\n");
+        let mut body = String::new();
+        content_reader(&gzipped, Box::new(&gzip[..]))
+            .unwrap()
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "compressed text");
+
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(b"deflated text").unwrap();
+        let zlib = zlib.finish().unwrap();
+        let deflated = response("HTTP/1.1 200 OK\r\nContent-Encoding: deflate\r\n\r\n");
+        let mut body = String::new();
+        content_reader(&deflated, Box::new(&zlib[..]))
+            .unwrap()
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "deflated text");
+
+        let brotli = response("HTTP/1.1 200 OK\r\nContent-Encoding: br\r\n\r\n");
+        assert!(content_reader(&brotli, Box::new(io::empty())).is_err());
+    }
+}
@@ -0,0 +1,355 @@
+//! # Checksum Verification
+//!
+//! This module checks a completed download against an expected digest. The digest is
+//! computed while the download runs: sequential writers feed it directly, and parts
+//! written out of order are hashed from the output file as soon as everything before
+//! them is on disk, so little is left to read once the last part arrives.
+//!
+//! ## Features
+//! - Supports SHA-256, SHA-512, SHA-1 and MD5 digests.
+//! - Parses `algorithm:hex` values and `SHA256SUMS`-style checksum files.
+//! - Hashes incrementally, from a sequential stream or from a file being filled in.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use md5::Md5;
+use sha1::Sha1;
+use sha2::digest::DynDigest;
+use sha2::{Digest, Sha256, Sha512};
+use crate::error::DownloaderError;
+use crate::output;
+
+/// Size of the buffer used to read back file contents for hashing.
+const READ_SIZE: usize = 64 * 1024;
+
+/// A supported digest algorithm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256,
+    Sha512,
+    Sha1,
+    Md5,
+}
+
+impl Algorithm {
+    /// Looks up an algorithm by name, e.g. `sha256` or `SHA-256`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().replace('-', "").as_str() {
+            "sha256" => Some(Algorithm::Sha256),
+            "sha512" => Some(Algorithm::Sha512),
+            "sha1" => Some(Algorithm::Sha1),
+            "md5" => Some(Algorithm::Md5),
+            _ => None,
+        }
+    }
+
+    /// The algorithm producing digests of `len` bytes.
+    fn from_digest_len(len: usize) -> Option<Self> {
+        match len {
+            32 => Some(Algorithm::Sha256),
+            64 => Some(Algorithm::Sha512),
+            20 => Some(Algorithm::Sha1),
+            16 => Some(Algorithm::Md5),
+            _ => None,
+        }
+    }
+
+    /// Length of the algorithm's digests in bytes.
+    pub fn digest_len(self) -> usize {
+        match self {
+            Algorithm::Sha256 => 32,
+            Algorithm::Sha512 => 64,
+            Algorithm::Sha1 => 20,
+            Algorithm::Md5 => 16,
+        }
+    }
+
+    fn hasher(self) -> Box<dyn DynDigest + Send> {
+        match self {
+            Algorithm::Sha256 => Box::new(Sha256::new()),
+            Algorithm::Sha512 => Box::new(Sha512::new()),
+            Algorithm::Sha1 => Box::new(Sha1::new()),
+            Algorithm::Md5 => Box::new(Md5::new()),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha512 => "sha512",
+            Algorithm::Sha1 => "sha1",
+            Algorithm::Md5 => "md5",
+        })
+    }
+}
+
+/// The digest a download is expected to have.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checksum {
+    /// The digest algorithm.
+    pub algorithm: Algorithm,
+    /// The expected digest.
+    pub digest: Vec<u8>,
+}
+
+impl Checksum {
+    /// Parses an `algorithm:hex` value such as `sha256:9f86d0…`.
+    ///
+    /// # Errors
+    /// Returns `DownloaderError::UserInputError` for unknown algorithms and digests that
+    /// are not hex or have the wrong length.
+    pub fn parse(value: &str) -> Result<Self, DownloaderError> {
+        let invalid = |reason: &str| {
+            DownloaderError::UserInputError(format!("Invalid checksum '{}': {}", value, reason))
+        };
+        let (name, hex) = value
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| invalid("expected <algorithm>:<hex digest>"))?;
+        let algorithm = Algorithm::from_name(name)
+            .ok_or_else(|| invalid("supported algorithms are sha256, sha512, sha1 and md5"))?;
+        let digest = decode_hex(hex).ok_or_else(|| invalid("digest is not hexadecimal"))?;
+        if digest.len() != algorithm.digest_len() {
+            return Err(invalid(&format!(
+                "a {} digest has {} hex digits",
+                algorithm,
+                algorithm.digest_len() * 2
+            )));
+        }
+        Ok(Self { algorithm, digest })
+    }
+
+    /// Finds the checksum of `file_name` in a checksum file such as `SHA256SUMS`.
+    ///
+    /// Lines have the form `<hex digest>  <file name>`, as written by `sha256sum` and
+    /// related tools; a `*` before the name (binary mode) is ignored. The algorithm is
+    /// taken from the length of the digest.
+    ///
+    /// # Errors
+    /// Returns `DownloaderError::FileError` if the file cannot be read, and
+    /// `DownloaderError::UserInputError` if it has no usable entry for `file_name`.
+    pub fn from_sums_file(path: &Path, file_name: &str) -> Result<Self, DownloaderError> {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            DownloaderError::FileError(format!("Could not read '{}': {}", path.display(), e))
+        })?;
+        Self::from_sums(&contents, file_name).ok_or_else(|| {
+            DownloaderError::UserInputError(format!(
+                "'{}' has no checksum for '{}'",
+                path.display(),
+                file_name
+            ))
+        })
+    }
+
+    fn from_sums(contents: &str, file_name: &str) -> Option<Self> {
+        contents.lines().find_map(|line| {
+            let (hex, name) = line.trim().split_once(char::is_whitespace)?;
+            let name = name.trim_start();
+            let name = name.strip_prefix('*').unwrap_or(name);
+            let name = name.strip_prefix("./").unwrap_or(name);
+            if name != file_name {
+                return None;
+            }
+            let digest = decode_hex(hex)?;
+            let algorithm = Algorithm::from_digest_len(digest.len())?;
+            Some(Self { algorithm, digest })
+        })
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.algorithm, encode_hex(&self.digest))
+    }
+}
+
+/// Computes the digest of a download and compares it with the expected one.
+///
+/// Bytes must be fed in file order, either with `update` or from the output file with
+/// `catch_up` and `read_rest`. A `Verifier` is shared between the workers of a download.
+pub struct Verifier {
+    expected: Checksum,
+    progress: Mutex<Progress>,
+}
+
+struct Progress {
+    hasher: Box<dyn DynDigest + Send>,
+    /// Number of bytes hashed so far.
+    hashed: u64,
+}
+
+impl Verifier {
+    /// Creates a verifier for a download expected to have the digest `expected`.
+    pub fn new(expected: Checksum) -> Self {
+        let hasher = expected.algorithm.hasher();
+        Self {
+            expected,
+            progress: Mutex::new(Progress { hasher, hashed: 0 }),
+        }
+    }
+
+    /// Hashes `data`, which directly follows everything hashed so far.
+    pub fn update(&self, data: &[u8]) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.hasher.update(data);
+        progress.hashed += data.len() as u64;
+    }
+
+    /// Hashes the bytes of `file` from where hashing stopped up to `frontier`.
+    ///
+    /// Returns immediately if another worker is already hashing; that worker, or a later
+    /// call, picks up the new bytes instead.
+    pub fn catch_up(&self, file: &File, frontier: u64) -> io::Result<()> {
+        match self.progress.try_lock() {
+            Ok(mut progress) => hash_file_range(&mut progress, file, frontier),
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// Hashes the bytes of `file` from where hashing stopped up to `len`, waiting for
+    /// other workers if necessary.
+    pub fn read_rest(&self, file: &File, len: u64) -> io::Result<()> {
+        hash_file_range(&mut self.progress.lock().unwrap(), file, len)
+    }
+
+    /// Compares the digest of everything hashed with the expected digest.
+    ///
+    /// # Errors
+    /// Returns `DownloaderError::ChecksumError` if the digests differ.
+    pub fn verify(&self) -> Result<(), DownloaderError> {
+        let digest = self.progress.lock().unwrap().hasher.finalize_reset();
+        if *digest == *self.expected.digest {
+            Ok(())
+        } else {
+            Err(DownloaderError::ChecksumError(format!(
+                "expected {}, got {}:{}",
+                self.expected,
+                self.expected.algorithm,
+                encode_hex(&digest)
+            )))
+        }
+    }
+}
+
+fn hash_file_range(progress: &mut Progress, file: &File, end: u64) -> io::Result<()> {
+    let mut buffer = vec![0u8; READ_SIZE];
+    while progress.hashed < end {
+        let len = (end - progress.hashed).min(READ_SIZE as u64) as usize;
+        let n = output::read_at(file, &mut buffer[..len], progress.hashed)?;
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "output file is shorter than expected"));
+        }
+        progress.hasher.update(&buffer[..n]);
+        progress.hashed += n as u64;
+    }
+    Ok(())
+}
+
+/// Forwards writes to `inner` and hashes them, in order, with a `Verifier`.
+pub struct HashingWriter<'a, W> {
+    inner: W,
+    verifier: &'a Verifier,
+}
+
+impl<'a, W: Write> HashingWriter<'a, W> {
+    /// Creates a writer that hashes everything written to `inner` with `verifier`.
+    pub fn new(inner: W, verifier: &'a Verifier) -> Self {
+        Self { inner, verifier }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.verifier.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex.trim();
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Algorithm, Checksum, HashingWriter, Verifier};
+    use crate::error::DownloaderError;
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    const HELLO_SHA256: &str = "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn test_parse_checksum() {
+        let checksum = Checksum::parse(HELLO_SHA256).unwrap();
+        assert_eq!(checksum.algorithm, Algorithm::Sha256);
+        assert_eq!(checksum.to_string(), HELLO_SHA256);
+        assert_eq!(
+            Checksum::parse("MD5:5D41402ABC4B2A76B9719D911017C592").unwrap().algorithm,
+            Algorithm::Md5
+        );
+
+        assert!(Checksum::parse("sha256").is_err());
+        assert!(Checksum::parse("crc32:12345678").is_err());
+        assert!(Checksum::parse("sha1:2cf24dba5fb0a30e").is_err());
+        assert!(Checksum::parse("sha256:zz").is_err());
+    }
+
+    #[test]
+    fn test_checksum_from_sums_file() {
+        let sums = "\
+            0000000000000000000000000000000000000000000000000000000000000000  other.iso\n\
+            2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824 *image.iso\n";
+        let checksum = Checksum::from_sums(sums, "image.iso").unwrap();
+        assert_eq!(checksum, Checksum::parse(HELLO_SHA256).unwrap());
+        assert_eq!(Checksum::from_sums(sums, "missing.iso"), None);
+
+        let path = std::env::temp_dir().join(format!("pd-sums-test-{}", std::process::id()));
+        fs::write(&path, "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d  ./hello.txt\n").unwrap();
+        let checksum = Checksum::from_sums_file(&path, "hello.txt").unwrap();
+        assert_eq!(checksum.algorithm, Algorithm::Sha1);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_verifier_sequential_and_from_file() {
+        let verifier = Verifier::new(Checksum::parse(HELLO_SHA256).unwrap());
+        let mut writer = HashingWriter::new(Vec::new(), &verifier);
+        writer.write_all(b"he").unwrap();
+        writer.write_all(b"llo").unwrap();
+        verifier.verify().unwrap();
+
+        let path = std::env::temp_dir().join(format!("pd-verify-test-{}", std::process::id()));
+        fs::write(&path, b"hello").unwrap();
+        let file = File::open(&path).unwrap();
+        let verifier = Verifier::new(Checksum::parse(HELLO_SHA256).unwrap());
+        verifier.catch_up(&file, 2).unwrap();
+        verifier.read_rest(&file, 5).unwrap();
+        verifier.verify().unwrap();
+
+        let verifier = Verifier::new(Checksum::parse(HELLO_SHA256).unwrap());
+        verifier.update(b"jello");
+        assert!(matches!(verifier.verify(), Err(DownloaderError::ChecksumError(_))));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -6,6 +6,81 @@
 //! ## Features
 //! - Configures the file download settings
 //! - Supports multiple concurrent connections
+//! - Limits how small the parts of a file may be
+//! - Limits how small a part may be split for an idle connection
+//! - Selects how downloaded parts are written to disk
+//! - Decides what happens to an existing output file
+//! - Enables resuming interrupted downloads
+//! - Selects where temporary files are kept
+//! - Configures how failed parts are retried
+//! - Limits how redirects are followed
+//! - Configures how TLS certificates are verified
+//! - Shares one caching DNS resolver between all connections
+//! - Optionally spreads connections over all of the server's addresses
+//! - Sets connect, read and whole-download timeouts
+//! - Connects through HTTP or SOCKS5 proxies
+//! - Authenticates to the server with Basic, Bearer or `.netrc` credentials
+//! - Sends a configurable `User-Agent`, `Referer` and extra request headers
+//! - Keeps cookies in a jar shared by all connections
+//! - Verifies the completed file against an expected checksum
+//! - Reports download progress to an observer
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use crate::auth::Auth;
+use crate::checksum::Checksum;
+use crate::connection::TlsOptions;
+use crate::cookies::CookieJar;
+use crate::dns::Resolver;
+use crate::error::DownloaderError;
+use crate::progress::ProgressObserver;
+use crate::proxy::ProxyOptions;
+use crate::request::RequestHeaders;
+use crate::retry::RetryPolicy;
+use crate::tcp::Timeouts;
+
+/// How the downloaded parts are written to disk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Preallocate the output file and let each part write at its own offset.
+    Preallocate,
+    /// Save each part to its own file and merge them once all parts are done.
+    PartFiles,
+}
+
+/// What happens when the output file already exists.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Fail before downloading anything.
+    #[default]
+    Fail,
+    /// Replace the existing file once the download is complete.
+    Overwrite,
+    /// Download to the first free name of the form `file (1).jpg`.
+    Rename,
+    /// Download nothing if the existing file is identical to the remote one, and replace
+    /// it otherwise; see [`crate::output::is_identical`].
+    SkipIfIdentical,
+}
+
+impl OverwritePolicy {
+    /// Parses a policy name: `fail`, `overwrite`, `rename` or `skip-identical`.
+    ///
+    /// # Errors
+    /// Returns `DownloaderError::UserInputError` for any other name.
+    pub fn parse(value: &str) -> Result<Self, DownloaderError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "fail" => Ok(OverwritePolicy::Fail),
+            "overwrite" => Ok(OverwritePolicy::Overwrite),
+            "rename" => Ok(OverwritePolicy::Rename),
+            "skip-identical" => Ok(OverwritePolicy::SkipIfIdentical),
+            _ => Err(DownloaderError::UserInputError(format!(
+                "Invalid overwrite policy '{}': expected fail, overwrite, rename or skip-identical",
+                value
+            ))),
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct DownloadConfig {
@@ -15,6 +90,56 @@ pub struct DownloadConfig {
     pub output_file: String,
     /// The number of concurrent connections to use.
     pub num_connections: usize,
+    /// The smallest part a file is divided into, so that small files are not fetched
+    /// over more connections than is worthwhile. Defaults to 4 KiB.
+    pub min_part_size: u64,
+    /// The smallest part that is split off a slow part for a connection that has
+    /// finished its own. Defaults to 1 MiB.
+    pub min_segment_size: u64,
+    /// How parts are written to disk. Defaults to `WriteMode::Preallocate`.
+    pub write_mode: WriteMode,
+    /// What happens when `output_file` already exists. Checked before any part is
+    /// requested. Defaults to `OverwritePolicy::Fail`.
+    pub overwrite: OverwritePolicy,
+    /// Keep a control file so an interrupted download can be continued, and continue
+    /// from an existing one. Defaults to `false`.
+    pub resume: bool,
+    /// The directory the `.partial` file and part files are kept in. Defaults to `None`,
+    /// the directory of the output file.
+    pub temp_dir: Option<PathBuf>,
+    /// How failed parts are retried.
+    pub retry: RetryPolicy,
+    /// The largest number of redirects followed for a single request. Defaults to `10`.
+    pub max_redirects: usize,
+    /// Follow redirects from `https` to `http` URLs. Defaults to `false`.
+    pub allow_insecure_redirects: bool,
+    /// How the certificates of `https` servers are verified.
+    pub tls: TlsOptions,
+    /// The resolver used for every connection of the download, so that its cache is
+    /// shared between parts. Defaults to the system DNS configuration.
+    pub resolver: Arc<Resolver>,
+    /// Start each connection at the next of the server's addresses, so parts are spread
+    /// over all of them. Defaults to `false`.
+    pub spread_addresses: bool,
+    /// Connect, read and whole-download timeouts.
+    pub timeouts: Timeouts,
+    /// The proxies connections go through. Defaults to connecting directly; use
+    /// `ProxyOptions::from_env` to follow the `*_PROXY` environment variables.
+    pub proxy: ProxyOptions,
+    /// How to authenticate to the server. Defaults to `Auth::None`, which still sends
+    /// the userinfo of `url`, if any.
+    pub auth: Auth,
+    /// The `User-Agent`, `Referer` and extra headers of every request. Defaults to the
+    /// downloader's own `User-Agent` only.
+    pub headers: RequestHeaders,
+    /// The cookie jar, loaded before the probe and updated from every response. Defaults
+    /// to `None`, which sends and keeps no cookies.
+    pub cookies: Option<Arc<CookieJar>>,
+    /// The digest the completed file must have. When it does not match, the download
+    /// fails and the file is left at `<output>.corrupt`. Defaults to `None`.
+    pub checksum: Option<Checksum>,
+    /// Receives progress events while the download runs. Defaults to `None`.
+    pub progress: Option<Arc<dyn ProgressObserver>>,
 }
 
 impl DownloadConfig {
@@ -32,13 +157,35 @@ impl DownloadConfig {
             url,
             output_file,
             num_connections,
+            min_part_size: 4 * 1024,
+            min_segment_size: 1024 * 1024,
+            write_mode: WriteMode::Preallocate,
+            overwrite: OverwritePolicy::Fail,
+            resume: false,
+            temp_dir: None,
+            retry: RetryPolicy::default(),
+            max_redirects: 10,
+            allow_insecure_redirects: false,
+            tls: TlsOptions::default(),
+            resolver: Arc::new(Resolver::default()),
+            spread_addresses: false,
+            timeouts: Timeouts::default(),
+            proxy: ProxyOptions::default(),
+            auth: Auth::None,
+            headers: RequestHeaders::default(),
+            cookies: None,
+            checksum: None,
+            progress: None,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::DownloadConfig;
+    use super::{DownloadConfig, OverwritePolicy, WriteMode};
+    use crate::auth::Auth;
+    use crate::proxy::ProxyOptions;
+    use crate::request::RequestHeaders;
 
     #[test]
     fn test_download_config_creation() {
@@ -50,5 +197,30 @@ mod tests {
         assert_eq!(config.url, "https://cobweb.cs.uga.edu/~perdisci/CSCI6760-F21/Project2-TestFiles/Uga-VII.jpg");
         assert_eq!(config.output_file, "output.jpg");
         assert_eq!(config.num_connections, 4);
+        assert_eq!(config.min_part_size, 4 * 1024);
+        assert_eq!(config.min_segment_size, 1024 * 1024);
+        assert_eq!(config.write_mode, WriteMode::Preallocate);
+        assert_eq!(config.overwrite, OverwritePolicy::Fail);
+        assert!(!config.resume);
+        assert_eq!(config.temp_dir, None);
+        assert_eq!(config.max_redirects, 10);
+        assert!(!config.allow_insecure_redirects);
+        assert!(!config.tls.insecure);
+        assert_eq!(config.resolver.options(), &Default::default());
+        assert!(!config.spread_addresses);
+        assert_eq!(config.timeouts.download, None);
+        assert_eq!(config.proxy, ProxyOptions::default());
+        assert_eq!(config.auth, Auth::None);
+        assert_eq!(config.headers, RequestHeaders::default());
+        assert!(config.cookies.is_none());
+        assert_eq!(config.checksum, None);
+        assert!(config.progress.is_none());
+    }
+
+    #[test]
+    fn test_parse_overwrite_policy() {
+        assert_eq!(OverwritePolicy::parse("rename").unwrap(), OverwritePolicy::Rename);
+        assert_eq!(OverwritePolicy::parse("Skip-Identical").unwrap(), OverwritePolicy::SkipIfIdentical);
+        assert!(OverwritePolicy::parse("clobber").is_err());
     }
 }
@@ -1,37 +1,339 @@
 //! # Connection Management
 //!
-//! This module handles the establishment of secure connections using TLS. It ensures
-//! robust communication between the client and the server by creating a secure socket.
+//! This module handles the establishment of connections to the server. Depending on the
+//! URL scheme the connection is either a plain TCP socket (`http`) or a socket secured
+//! with TLS (`https`), both exposed through the common `Stream` type.
 //!
 //! ## Features
-//! - Establishes a TCP connection.
-//! - Secures the connection using TLS.
+//! - Establishes a TCP connection on the URL's explicit or default port.
+//! - Races connection attempts to all of the server's addresses (Happy Eyeballs).
+//! - Secures the connection using TLS for `https` URLs.
+//! - Verifies server certificates against the system roots and optional extra CAs.
+//! - Tunnels through the proxy configured for the URL, if any.
 
+use std::fs;
+use std::io::{self, Read, Write};
 use std::net::{IpAddr, TcpStream};
-use native_tls::{TlsConnector, TlsStream, HandshakeError};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use native_tls::{Certificate, TlsConnector, TlsStream, HandshakeError};
+use url::Url;
+use crate::config::DownloadConfig;
 use crate::error::DownloaderError;
-use crate::tcp;
+use crate::tcp::{self, Timeouts};
+use crate::{dns, proxy};
+
+/// How the certificates of `https` servers are verified.
+///
+/// The `TlsConnector` is built from these options on first use and shared by every
+/// connection made with them or a clone of them, so that CA files are read only once per
+/// download. Set the options before the first connection.
+#[derive(Clone, Debug, Default)]
+pub struct TlsOptions {
+    /// Accept any certificate for any hostname. Defaults to `false`; this makes the
+    /// connection open to interception and should only be used for testing.
+    pub insecure: bool,
+    /// A PEM file with CA certificates to trust in addition to the system roots.
+    pub ca_bundle: Option<PathBuf>,
+    /// A directory of PEM files with CA certificates to trust in addition to the
+    /// system roots.
+    pub ca_dir: Option<PathBuf>,
+    /// The connector, once built.
+    connector: Arc<OnceLock<TlsConnector>>,
+}
+
+impl PartialEq for TlsOptions {
+    fn eq(&self, other: &Self) -> bool {
+        (self.insecure, &self.ca_bundle, &self.ca_dir) == (other.insecure, &other.ca_bundle, &other.ca_dir)
+    }
+}
+
+impl Eq for TlsOptions {}
+
+impl TlsOptions {
+    /// Returns the `TlsConnector` that verifies certificates according to these options,
+    /// building it on first use.
+    ///
+    /// # Errors
+    /// Returns a `DownloaderError` if a CA bundle or directory cannot be read, is not
+    /// valid PEM or contains no certificates. Nothing is cached then, so the next call
+    /// tries again.
+    pub fn connector(&self) -> Result<TlsConnector, DownloaderError> {
+        if let Some(connector) = self.connector.get() {
+            return Ok(connector.clone());
+        }
+        let connector = self.build_connector()?;
+        Ok(self.connector.get_or_init(|| connector).clone())
+    }
+
+    fn build_connector(&self) -> Result<TlsConnector, DownloaderError> {
+        let mut builder = TlsConnector::builder();
+        if self.insecure {
+            builder
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true);
+        }
+        if let Some(path) = &self.ca_bundle {
+            for certificate in load_ca_bundle(path)? {
+                builder.add_root_certificate(certificate);
+            }
+        }
+        if let Some(dir) = &self.ca_dir {
+            for certificate in load_ca_dir(dir)? {
+                builder.add_root_certificate(certificate);
+            }
+        }
+        builder.build().map_err(DownloaderError::TlsError)
+    }
+}
+
+/// Reads every certificate from a PEM bundle.
+fn load_ca_bundle(path: &Path) -> Result<Vec<Certificate>, DownloaderError> {
+    let pem = fs::read(path).map_err(|e| {
+        DownloaderError::FileError(format!("Cannot read CA bundle {}: {}", path.display(), e))
+    })?;
+    let certificates = certificates_from_pem(&pem).map_err(|reason| {
+        DownloaderError::FileError(format!("Invalid CA bundle {}: {}", path.display(), reason))
+    })?;
+    if certificates.is_empty() {
+        return Err(DownloaderError::FileError(format!(
+            "No certificates found in CA bundle {}",
+            path.display()
+        )));
+    }
+    Ok(certificates)
+}
+
+/// Parses every certificate of a PEM file, like OpenSSL's `stack_from_pem`: the body of
+/// each `CERTIFICATE` block is base64-decoded and parsed as DER. Other blocks, such as
+/// keys, and any text between blocks are skipped.
+///
+/// # Errors
+/// Returns the reason if a block is not terminated, or a certificate block is not valid
+/// base64 or holds no valid certificate.
+fn certificates_from_pem(pem: &[u8]) -> Result<Vec<Certificate>, String> {
+    let text = String::from_utf8_lossy(pem);
+    let mut lines = text.lines().map(str::trim);
+    let mut certificates = Vec::new();
+    while let Some(line) = lines.next() {
+        let Some(label) = line.strip_prefix("-----BEGIN ").and_then(|rest| rest.strip_suffix("-----")) else {
+            continue;
+        };
+        let end = format!("-----END {}-----", label);
+        let mut body = String::new();
+        loop {
+            match lines.next() {
+                Some(line) if line == end => break,
+                Some(line) => body.push_str(line),
+                None => return Err(format!("the {} block is not terminated", label)),
+            }
+        }
+        if matches!(label, "CERTIFICATE" | "X509 CERTIFICATE") {
+            let der = BASE64
+                .decode(&body)
+                .map_err(|e| format!("a certificate is not valid base64: {}", e))?;
+            let certificate = Certificate::from_der(&der).map_err(|e| format!("invalid certificate: {}", e))?;
+            certificates.push(certificate);
+        }
+    }
+    Ok(certificates)
+}
+
+/// Reads the certificates from every PEM file in `dir`; other files are skipped.
+fn load_ca_dir(dir: &Path) -> Result<Vec<Certificate>, DownloaderError> {
+    let unreadable = |e: io::Error| {
+        DownloaderError::FileError(format!("Cannot read CA directory {}: {}", dir.display(), e))
+    };
+    let mut paths = fs::read_dir(dir)
+        .map_err(unreadable)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(unreadable)?;
+    paths.sort();
+
+    let certificates: Vec<Certificate> = paths
+        .iter()
+        .filter(|path| path.is_file())
+        .filter_map(|path| fs::read(path).ok())
+        .filter_map(|pem| certificates_from_pem(&pem).ok())
+        .flatten()
+        .collect();
+    if certificates.is_empty() {
+        return Err(DownloaderError::FileError(format!(
+            "No certificates found in CA directory {}",
+            dir.display()
+        )));
+    }
+    Ok(certificates)
+}
+
+/// A connection to the server, either plain TCP or TLS over TCP.
+pub enum Stream {
+    /// Unencrypted connection used for `http` URLs.
+    Plain(TcpStream),
+    /// TLS connection used for `https` URLs.
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl Stream {
+    /// Returns the underlying TCP socket.
+    pub fn get_ref(&self) -> &TcpStream {
+        match self {
+            Stream::Plain(stream) => stream,
+            Stream::Tls(stream) => stream.get_ref(),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.flush(),
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Resolves the host named in `url` and establishes a connection to it.
+///
+/// All resolved addresses are tried, IPv6 and IPv4 interleaved. With
+/// `config.spread_addresses` set, each call starts with the next address, so the parts
+/// of a download are spread over the server's addresses. When `config.proxy` has a
+/// proxy for the URL, the connection is tunnelled through it instead and the proxy
+/// resolves the host.
+///
+/// # Parameters
+/// - `url`: The URL being requested.
+/// - `config`: The download configuration, with the resolver, proxy and TLS options.
+///
+/// # Returns
+/// A `Stream` wrapped in a `Result`, representing the connection.
+pub fn connect(url: &Url, config: &DownloadConfig) -> Result<Stream, DownloaderError> {
+    let (hostname, port) = target(url)?;
+    if let Some(proxy) = config.proxy.for_url(url) {
+        let tcp_stream = proxy::open_tunnel(proxy, hostname, port, config)?;
+        return secure(url, hostname, tcp_stream, config);
+    }
+
+    let addresses = if config.spread_addresses {
+        config.resolver.lookup_round_robin(hostname)?
+    } else {
+        dns::interleave_families(&config.resolver.lookup(hostname)?)
+    };
+    establish_connection(url, &addresses, config)
+}
+
+/// Establishes a connection to the server named in `url` at one of the given addresses.
+///
+/// The port is taken from the URL, falling back to the scheme's default port, and the
+/// scheme decides whether the connection is wrapped in TLS. When several addresses are
+/// given, connection attempts are raced as described in `tcp::establish_tcp_socket_any`.
+///
+/// # Parameters
+/// - `url`: The URL being requested.
+/// - `addresses`: The IP addresses of the server, most preferred first.
+/// - `config`: The download configuration, with the TLS options and timeouts.
+///
+/// # Returns
+/// A `Stream` wrapped in a `Result`, representing the connection.
+///
+/// # Errors
+/// Returns a `DownloaderError` if the scheme is unsupported or the connection fails.
+pub fn establish_connection(
+    url: &Url,
+    addresses: &[IpAddr],
+    config: &DownloadConfig,
+) -> Result<Stream, DownloaderError> {
+    let (hostname, port) = target(url)?;
+    let tcp_stream = tcp::establish_tcp_socket_any(addresses, port, &config.timeouts)?;
+    secure(url, hostname, tcp_stream, config)
+}
+
+/// Returns the host and port to connect to for `url`.
+///
+/// # Errors
+/// Returns a `DownloaderError` if the URL has no host or its scheme is unsupported.
+fn target(url: &Url) -> Result<(&str, u16), DownloaderError> {
+    let hostname = url
+        .host_str()
+        .ok_or(DownloaderError::UrlParseError(url::ParseError::EmptyHost))?;
+    let port = url.port_or_known_default().ok_or_else(|| {
+        DownloaderError::UserInputError(format!("No port known for scheme '{}'", url.scheme()))
+    })?;
+    match url.scheme() {
+        "http" | "https" => Ok((hostname, port)),
+        scheme => Err(DownloaderError::UserInputError(format!(
+            "Unsupported URL scheme '{}'",
+            scheme
+        ))),
+    }
+}
+
+/// Wraps a TCP connection to the server of `url` in TLS if the scheme is `https`.
+fn secure(
+    url: &Url,
+    hostname: &str,
+    tcp_stream: TcpStream,
+    config: &DownloadConfig,
+) -> Result<Stream, DownloaderError> {
+    if url.scheme() == "https" {
+        Ok(Stream::Tls(Box::new(tls_handshake(hostname, tcp_stream, &config.tls)?)))
+    } else {
+        Ok(Stream::Plain(tcp_stream))
+    }
+}
 
 /// Establishes a secure TLS connection to the given hostname and IP address.
 ///
 /// # Parameters
 /// - `hostname`: The hostname of the server.
 /// - `ip`: The IP address of the server.
+/// - `port`: The port number.
+/// - `tls`: How the server's certificate is verified.
+/// - `timeouts`: The connect timeout, and the idle timeout set on the socket.
 ///
 /// # Returns
 /// A `TlsStream` wrapped in a `Result`, representing the secure connection.
+///
+/// # Errors
+/// Returns `DownloaderError::TlsError` if the handshake fails, including when the
+/// certificate cannot be verified.
 pub fn establish_tls_connection(
     hostname: &str,
     ip: IpAddr,
+    port: u16,
+    tls: &TlsOptions,
+    timeouts: &Timeouts,
 ) -> Result<TlsStream<TcpStream>, DownloaderError> {
-    let tcp_stream = tcp::establish_tcp_socket(ip, 443)?;
-
-    let connector = TlsConnector::builder()
-        .danger_accept_invalid_certs(true)
-        .build()
-        .map_err(|e| DownloaderError::TlsError(e))?;
+    let tcp_stream = tcp::establish_tcp_socket(ip, port, timeouts)?;
+    tls_handshake(hostname, tcp_stream, tls)
+}
 
-    connector
+/// Secures an established TCP connection with TLS.
+fn tls_handshake(
+    hostname: &str,
+    tcp_stream: TcpStream,
+    tls: &TlsOptions,
+) -> Result<TlsStream<TcpStream>, DownloaderError> {
+    tls.connector()?
         .connect(hostname, tcp_stream)
         .map_err(|e| match e {
             HandshakeError::Failure(e) => DownloaderError::TlsError(e),
@@ -43,20 +345,145 @@ pub fn establish_tls_connection(
 
 #[cfg(test)]
 mod tests {
-    use super::establish_tls_connection;
-    use std::net::IpAddr;
+    use super::{certificates_from_pem, establish_connection, establish_tls_connection, Stream, TlsOptions};
+    use crate::config::DownloadConfig;
+    use crate::error::DownloaderError;
+    use crate::tcp::Timeouts;
+    use native_tls::{Identity, TlsAcceptor};
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::{IpAddr, TcpListener};
+    use std::path::{Path, PathBuf};
+    use std::thread;
+    use url::Url;
 
     #[test]
     fn test_tls_connection() {
         let ip = "93.184.216.34".parse::<IpAddr>().unwrap();
-        let result = establish_tls_connection("example.com", ip);
+        let result = establish_tls_connection("example.com", ip, 443, &TlsOptions::default(), &Timeouts::default());
         assert!(result.is_ok());
     }
 
     #[test]
     fn test_invalid_tls_connection() {
         let ip = "127.0.0.1".parse::<IpAddr>().unwrap();
-        let result = establish_tls_connection("invalid.com", ip);
+        let result = establish_tls_connection("invalid.com", ip, 443, &TlsOptions::default(), &Timeouts::default());
+        assert!(result.is_err());
+    }
+
+    fn test_data(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data").join(name)
+    }
+
+    /// Accepts `connections` TLS connections with a certificate for `localhost` signed by
+    /// the test CA in `tests/data`, and greets each client that completes the handshake.
+    fn serve_tls(connections: usize) -> (u16, thread::JoinHandle<()>) {
+        let identity = Identity::from_pkcs8(
+            include_bytes!("../tests/data/localhost.pem"),
+            include_bytes!("../tests/data/localhost-key.pem"),
+        )
+        .unwrap();
+        let acceptor = TlsAcceptor::new(identity).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            for _ in 0..connections {
+                let (socket, _) = listener.accept().unwrap();
+                if let Ok(mut stream) = acceptor.accept(socket) {
+                    let _ = stream.write_all(b"hello");
+                }
+            }
+        });
+        (port, handle)
+    }
+
+    fn greeting(port: u16, tls: &TlsOptions) -> Result<String, DownloaderError> {
+        let url = Url::parse(&format!("https://localhost:{}/", port)).unwrap();
+        let mut config = DownloadConfig::new(url.to_string(), "output.bin".to_string(), 1);
+        config.tls = tls.clone();
+        let mut stream = establish_connection(&url, &["127.0.0.1".parse().unwrap()], &config)?;
+        let mut greeting = String::new();
+        stream.read_to_string(&mut greeting)?;
+        Ok(greeting)
+    }
+
+    #[test]
+    fn test_tls_verification() {
+        let (port, server) = serve_tls(4);
+
+        // The test CA is not a system root, so the default options reject the server.
+        let result = greeting(port, &TlsOptions::default());
+        assert!(matches!(result, Err(DownloaderError::TlsError(_))));
+
+        let bundle = TlsOptions { ca_bundle: Some(test_data("ca.pem")), ..TlsOptions::default() };
+        assert_eq!(greeting(port, &bundle).unwrap(), "hello");
+
+        let dir = TlsOptions { ca_dir: Some(test_data("")), ..TlsOptions::default() };
+        assert_eq!(greeting(port, &dir).unwrap(), "hello");
+
+        let insecure = TlsOptions { insecure: true, ..TlsOptions::default() };
+        assert_eq!(greeting(port, &insecure).unwrap(), "hello");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_missing_ca_bundle() {
+        let tls = TlsOptions { ca_bundle: Some(test_data("missing.pem")), ..TlsOptions::default() };
+        assert!(matches!(tls.connector(), Err(DownloaderError::FileError(_))));
+        let tls = TlsOptions { ca_bundle: Some(test_data("localhost-key.pem")), ..TlsOptions::default() };
+        assert!(tls.connector().is_err());
+    }
+
+    #[test]
+    fn test_certificates_from_pem() {
+        let ca = include_str!("../tests/data/ca.pem");
+        let key = include_str!("../tests/data/localhost-key.pem");
+        let localhost = include_str!("../tests/data/localhost.pem");
+        assert_eq!(certificates_from_pem(ca.as_bytes()).unwrap().len(), 1);
+        let bundle = format!("# test bundle\n{}{}\n{}", ca, key, localhost);
+        assert_eq!(certificates_from_pem(bundle.as_bytes()).unwrap().len(), 2);
+        assert!(certificates_from_pem(key.as_bytes()).unwrap().is_empty());
+
+        let truncated = ca.replace("-----END CERTIFICATE-----", "");
+        assert!(certificates_from_pem(truncated.as_bytes()).is_err());
+        let garbled = "-----BEGIN CERTIFICATE-----\nnot*base64\n-----END CERTIFICATE-----\n";
+        assert!(certificates_from_pem(garbled.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_connector_is_built_once() {
+        let dir = std::env::temp_dir().join(format!("pd-connector-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let bundle = dir.join("ca.pem");
+        fs::copy(test_data("ca.pem"), &bundle).unwrap();
+        let tls = TlsOptions { ca_bundle: Some(bundle.clone()), ..TlsOptions::default() };
+        tls.connector().unwrap();
+
+        // Clones share the connector, so the bundle is not read again.
+        fs::remove_file(&bundle).unwrap();
+        tls.clone().connector().unwrap();
+        let fresh = TlsOptions { ca_bundle: Some(bundle), ..TlsOptions::default() };
+        assert!(fresh.connector().is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_plain_connection_uses_explicit_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let url = Url::parse(&format!("http://localhost:{}/file.bin", port)).unwrap();
+
+        let config = DownloadConfig::new(url.to_string(), "output.bin".to_string(), 1);
+        let stream = establish_connection(&url, &["127.0.0.1".parse().unwrap()], &config).unwrap();
+        assert!(matches!(stream, Stream::Plain(_)));
+        assert_eq!(stream.get_ref().peer_addr().unwrap().port(), port);
+    }
+
+    #[test]
+    fn test_unsupported_scheme() {
+        let url = Url::parse("ftp://localhost/file.bin").unwrap();
+        let config = DownloadConfig::new(url.to_string(), "output.bin".to_string(), 1);
+        let result = establish_connection(&url, &["127.0.0.1".parse().unwrap()], &config);
         assert!(result.is_err());
     }
 }
@@ -0,0 +1,393 @@
+//! # Cookie Jar
+//!
+//! This module keeps the cookies of a download in a Netscape-format cookie file, the
+//! format written by curl, wget and browser export tools. The jar is loaded before the
+//! probe, sends matching cookies with every request, takes in `Set-Cookie` headers from
+//! every response and is shared by all workers, so cookies a server sets on the probe
+//! are sent with the part requests.
+//!
+//! ## Features
+//! - Reads and writes Netscape cookie files, including `#HttpOnly_` entries.
+//! - Matches cookies by domain, path, `Secure` flag and expiry.
+//! - Stores `Set-Cookie` headers with their `Domain`, `Path`, `Expires` and `Max-Age`.
+//! - Refuses cookies for domains other than the host that sets them.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use url::Url;
+use crate::error::DownloaderError;
+
+/// Prefix of cookie file lines for cookies with the `HttpOnly` attribute.
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+/// A cookie, as stored in a Netscape cookie file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cookie {
+    /// The domain the cookie is sent to, in lowercase without a leading dot.
+    pub domain: String,
+    /// Whether the cookie is also sent to subdomains of `domain`.
+    pub include_subdomains: bool,
+    /// The path prefix the cookie is sent for.
+    pub path: String,
+    /// Whether the cookie is only sent over `https`.
+    pub secure: bool,
+    /// Whether the cookie was set with `HttpOnly`.
+    pub http_only: bool,
+    /// When the cookie expires, in seconds since the Unix epoch; `0` for a session cookie.
+    pub expires: u64,
+    /// Name of the cookie.
+    pub name: String,
+    /// Value of the cookie.
+    pub value: String,
+}
+
+impl Cookie {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires != 0 && self.expires <= now
+    }
+
+    fn matches(&self, url: &Url, now: u64) -> bool {
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        let domain_matches = host == self.domain || (self.include_subdomains && is_subdomain(&host, &self.domain));
+        domain_matches
+            && path_matches(url.path(), &self.path)
+            && (!self.secure || url.scheme() == "https")
+            && !self.is_expired(now)
+    }
+}
+
+/// The cookies of a download, optionally backed by a cookie file.
+#[derive(Debug, Default)]
+pub struct CookieJar {
+    path: Option<PathBuf>,
+    cookies: Mutex<Vec<Cookie>>,
+}
+
+impl CookieJar {
+    /// Creates an empty jar that is kept in memory only.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a jar from a Netscape cookie file. The jar is written back to the same file
+    /// by `save`; a missing file is created then.
+    ///
+    /// # Errors
+    /// Returns a `DownloaderError` if the file exists but cannot be read.
+    pub fn load(path: &Path) -> Result<Self, DownloaderError> {
+        let cookies = match fs::read_to_string(path) {
+            Ok(contents) => parse(&contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path: Some(path.to_path_buf()), cookies: Mutex::new(cookies) })
+    }
+
+    /// Returns every cookie in the jar.
+    pub fn cookies(&self) -> Vec<Cookie> {
+        self.cookies.lock().unwrap().clone()
+    }
+
+    /// Returns the value of the `Cookie` header for a request to `url`, or `None` if no
+    /// cookie matches. Cookies with longer paths come first.
+    pub fn header(&self, url: &Url) -> Option<String> {
+        let now = unix_now();
+        let cookies = self.cookies.lock().unwrap();
+        let mut matching: Vec<&Cookie> = cookies.iter().filter(|cookie| cookie.matches(url, now)).collect();
+        if matching.is_empty() {
+            return None;
+        }
+        matching.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.len()));
+        let pairs: Vec<String> = matching.iter().map(|cookie| format!("{}={}", cookie.name, cookie.value)).collect();
+        Some(pairs.join("; "))
+    }
+
+    /// Stores the cookies of `Set-Cookie` header values received from `url`.
+    ///
+    /// A cookie replaces one with the same name, domain and path, and an expired cookie
+    /// removes it. Cookies whose `Domain` does not cover the host of `url` are ignored.
+    pub fn store<'a>(&self, url: &Url, set_cookies: impl IntoIterator<Item = &'a str>) {
+        let now = unix_now();
+        let mut cookies = self.cookies.lock().unwrap();
+        for value in set_cookies {
+            let Some(cookie) = parse_set_cookie(url, value, now) else {
+                continue;
+            };
+            cookies.retain(|existing| {
+                !(existing.name == cookie.name && existing.domain == cookie.domain && existing.path == cookie.path)
+            });
+            if !cookie.is_expired(now) {
+                cookies.push(cookie);
+            }
+        }
+    }
+
+    /// Writes the jar back to its cookie file, if it has one. Expired cookies are left
+    /// out.
+    pub fn save(&self) -> Result<(), DownloaderError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let now = unix_now();
+        let mut out = String::from("# Netscape HTTP Cookie File\n");
+        for cookie in self.cookies.lock().unwrap().iter().filter(|cookie| !cookie.is_expired(now)) {
+            let flag = |value: bool| if value { "TRUE" } else { "FALSE" };
+            out.push_str(&format!(
+                "{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                if cookie.http_only { HTTP_ONLY_PREFIX } else { "" },
+                if cookie.include_subdomains { "." } else { "" },
+                cookie.domain,
+                flag(cookie.include_subdomains),
+                cookie.path,
+                flag(cookie.secure),
+                cookie.expires,
+                cookie.name,
+                cookie.value
+            ));
+        }
+        fs::write(path, out)?;
+        Ok(())
+    }
+}
+
+/// Parses the contents of a Netscape cookie file. Comments, blank lines and malformed
+/// lines are skipped.
+pub fn parse(contents: &str) -> Vec<Cookie> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (line, http_only) = match line.strip_prefix(HTTP_ONLY_PREFIX) {
+                Some(rest) => (rest, true),
+                None => (line, false),
+            };
+            if line.trim().is_empty() || line.starts_with('#') {
+                return None;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [domain, include_subdomains, path, secure, expires, name, value] = fields[..] else {
+                return None;
+            };
+            Some(Cookie {
+                domain: domain.trim_start_matches('.').to_ascii_lowercase(),
+                include_subdomains: include_subdomains.eq_ignore_ascii_case("TRUE"),
+                path: path.to_string(),
+                secure: secure.eq_ignore_ascii_case("TRUE"),
+                http_only,
+                expires: expires.parse().ok()?,
+                name: name.to_string(),
+                value: value.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Parses a `Set-Cookie` header value received from `url`.
+fn parse_set_cookie(url: &Url, value: &str, now: u64) -> Option<Cookie> {
+    let host = url.host_str()?.to_ascii_lowercase();
+    let mut attributes = value.split(';');
+    let (name, cookie_value) = attributes.next()?.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut cookie = Cookie {
+        domain: host.clone(),
+        include_subdomains: false,
+        path: default_path(url.path()),
+        secure: false,
+        http_only: false,
+        expires: 0,
+        name: name.to_string(),
+        value: cookie_value.trim().to_string(),
+    };
+    let mut max_age = None;
+    for attribute in attributes {
+        let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+        let value = value.trim();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "domain" if !value.is_empty() => {
+                let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                // A host may only set cookies for itself and its parent domains, and
+                // not for a bare top-level domain.
+                if domain != host && !(is_subdomain(&host, &domain) && domain.contains('.')) {
+                    return None;
+                }
+                cookie.domain = domain;
+                cookie.include_subdomains = true;
+            }
+            "path" if value.starts_with('/') => cookie.path = value.to_string(),
+            "secure" => cookie.secure = true,
+            "httponly" => cookie.http_only = true,
+            "max-age" => max_age = value.parse::<i64>().ok(),
+            "expires" => {
+                if let Some(expires) = parse_http_date(value) {
+                    // An expiry of 0 would mean a session cookie, so use 1 for the past.
+                    cookie.expires = expires.max(1);
+                }
+            }
+            _ => {}
+        }
+    }
+    // Max-Age takes precedence over Expires.
+    match max_age {
+        Some(age) if age <= 0 => cookie.expires = 1,
+        Some(age) => cookie.expires = now.saturating_add(age as u64),
+        None => {}
+    }
+    Some(cookie)
+}
+
+/// Whether `host` is a subdomain of `domain`.
+fn is_subdomain(host: &str, domain: &str) -> bool {
+    host.strip_suffix(domain).is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+/// Whether a request for `path` is covered by a cookie path (RFC 6265, section 5.1.4).
+fn path_matches(path: &str, cookie_path: &str) -> bool {
+    match path.strip_prefix(cookie_path) {
+        Some(rest) => rest.is_empty() || cookie_path.ends_with('/') || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// The path a cookie is sent for when `Set-Cookie` gives none: the directory of the
+/// request path.
+fn default_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(end) => path[..end].to_string(),
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// Parses a date as used in `Expires`, e.g. `Wed, 21 Oct 2015 07:28:00 GMT` or the
+/// older `Wed, 21-Oct-2015 07:28:00 GMT`.
+///
+/// # Returns
+/// Seconds since the Unix epoch, or `None` if the date cannot be parsed.
+pub fn parse_http_date(value: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+    let (mut day, mut month, mut year, mut time) = (None, None, None, None);
+    for token in value.split([' ', '-', ',']).filter(|token| !token.is_empty()) {
+        let lower = token.to_ascii_lowercase();
+        if let Some(index) = MONTHS.iter().position(|name| lower.starts_with(name)) {
+            month = Some(index as u64 + 1);
+        } else if token.contains(':') {
+            let parts: Vec<u64> = token.split(':').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+            let [hours, minutes, seconds] = parts[..] else {
+                return None;
+            };
+            time = Some(hours * 3600 + minutes * 60 + seconds);
+        } else if let Ok(number) = token.parse::<u64>() {
+            if day.is_none() && token.len() <= 2 {
+                day = Some(number);
+            } else {
+                // Two-digit years: 70-99 are 19xx, 00-69 are 20xx.
+                year = Some(match number {
+                    0..=69 => number + 2000,
+                    70..=99 => number + 1900,
+                    _ => number,
+                });
+            }
+        }
+    }
+
+    let (day, month, year, time) = (day?, month?, year?, time?);
+    if !(1..=31).contains(&day) || year < 1970 {
+        return None;
+    }
+    // Days since the epoch of a proleptic Gregorian date.
+    let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = y / 400;
+    let year_of_era = y % 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    Some(days * 86_400 + time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, parse_http_date, CookieJar};
+    use std::fs;
+    use url::Url;
+
+    #[test]
+    fn test_parse_http_date() {
+        assert_eq!(parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT"), Some(1_445_412_480));
+        assert_eq!(parse_http_date("Wed, 21-Oct-15 07:28:00 GMT"), Some(1_445_412_480));
+        assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        assert_eq!(parse_http_date("29 Feb 2024 12:00:00"), Some(1_709_208_000));
+        assert_eq!(parse_http_date("yesterday"), None);
+    }
+
+    #[test]
+    fn test_parse_cookie_file() {
+        let cookies = parse(
+            "# Netscape HTTP Cookie File\n\
+             .example.com\tTRUE\t/\tFALSE\t0\tsession\tabc\n\
+             #HttpOnly_files.example.com\tFALSE\t/private\tTRUE\t4102444800\ttoken\txyz\n\
+             malformed line\n",
+        );
+        assert_eq!(cookies.len(), 2);
+        assert_eq!((cookies[0].domain.as_str(), cookies[0].include_subdomains), ("example.com", true));
+        assert!(cookies[1].http_only && cookies[1].secure);
+        assert_eq!(cookies[1].expires, 4_102_444_800);
+    }
+
+    #[test]
+    fn test_cookies_match_domain_path_and_scheme() {
+        let jar = CookieJar::new();
+        let url = Url::parse("https://files.example.com/downloads/a.bin").unwrap();
+        jar.store(
+            &url,
+            [
+                "session=abc; Path=/; Domain=.example.com",
+                "dir=1",
+                "secure=2; Secure; HttpOnly",
+                "evil=1; Domain=other.com",
+                "tld=1; Domain=com",
+            ],
+        );
+        assert_eq!(jar.cookies().len(), 3);
+
+        assert_eq!(jar.header(&url).as_deref(), Some("dir=1; secure=2; session=abc"));
+        let plain = Url::parse("http://files.example.com/downloads/b.bin").unwrap();
+        assert_eq!(jar.header(&plain).as_deref(), Some("dir=1; session=abc"));
+        let sibling = Url::parse("https://www.example.com/downloads/a.bin").unwrap();
+        assert_eq!(jar.header(&sibling).as_deref(), Some("session=abc"));
+        let elsewhere = Url::parse("https://files.example.com/downloadsX").unwrap();
+        assert_eq!(jar.header(&elsewhere).as_deref(), Some("session=abc"));
+        assert_eq!(jar.header(&Url::parse("https://other.com/").unwrap()), None);
+
+        // Replacing and expiring cookies.
+        jar.store(&url, ["session=def; Path=/; Domain=example.com", "dir=; Max-Age=0"]);
+        assert_eq!(jar.header(&plain).as_deref(), Some("session=def"));
+        jar.store(&url, ["session=; Path=/; Domain=example.com; Expires=Thu, 01 Jan 1970 00:00:01 GMT"]);
+        assert_eq!(jar.header(&plain), None);
+    }
+
+    #[test]
+    fn test_jar_is_saved_and_loaded() {
+        let path = std::env::temp_dir().join(format!("pd-cookies-test-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let jar = CookieJar::load(&path).unwrap();
+        let url = Url::parse("https://example.com/a.bin").unwrap();
+        jar.store(&url, ["id=42; Max-Age=3600; HttpOnly", "pref=dark; Domain=example.com"]);
+        jar.save().unwrap();
+
+        let loaded = CookieJar::load(&path).unwrap();
+        assert_eq!(loaded.cookies(), jar.cookies());
+        assert!(fs::read_to_string(&path).unwrap().contains("#HttpOnly_example.com\tFALSE\t/\tFALSE\t"));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -4,36 +4,228 @@
 //! component of the downloader that ensures the correct server is contacted.
 //!
 //! ## Features
-//! - Resolves a hostname to an IP address.
+//! - Resolves hostnames with `trust-dns-resolver`.
+//! - Reads nameservers from the system or a given resolv.conf, or uses an explicit list.
+//! - Queries nameservers over UDP or TCP.
+//! - Caches answers for their TTL, shared by every connection that uses the same `Resolver`.
+//! - Orders addresses for Happy Eyeballs and hands them out round-robin.
 //! - Handles errors gracefully.
 
-use std::net::{IpAddr, ToSocketAddrs};
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use trust_dns_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+use trust_dns_resolver::Resolver as TrustResolver;
 use crate::error::DownloaderError;
 
+/// Which nameservers a `Resolver` queries, and how.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DnsOptions {
+    /// A resolv.conf file to read nameservers and options from. Defaults to the system
+    /// configuration.
+    pub resolv_conf: Option<PathBuf>,
+    /// Nameservers to query instead of those from the resolv.conf.
+    pub nameservers: Vec<SocketAddr>,
+    /// Query nameservers over TCP only, instead of UDP with a TCP fallback.
+    pub use_tcp: bool,
+}
+
+/// A caching DNS resolver.
+///
+/// The underlying resolver is created on the first lookup. Answers are cached for their
+/// TTL, so sharing one `Resolver` (e.g. through `DownloadConfig`) lets every part of a
+/// download reuse the first lookup.
+pub struct Resolver {
+    options: DnsOptions,
+    inner: Mutex<Option<Arc<TrustResolver>>>,
+    turn: AtomicUsize,
+}
+
+impl Resolver {
+    /// Creates a resolver with the given options.
+    pub fn new(options: DnsOptions) -> Self {
+        Self {
+            options,
+            inner: Mutex::new(None),
+            turn: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the options this resolver was created with.
+    pub fn options(&self) -> &DnsOptions {
+        &self.options
+    }
+
+    /// Resolves `hostname` to all of its IP addresses.
+    ///
+    /// IP address literals are returned as they are, without a query.
+    ///
+    /// # Parameters
+    /// - `hostname`: The hostname to resolve.
+    ///
+    /// # Returns
+    /// The resolved addresses, in the order the nameserver returned them.
+    ///
+    /// # Errors
+    /// Returns `DownloaderError::DnsError` if the configuration cannot be read, the lookup
+    /// fails or no address is found.
+    pub fn lookup(&self, hostname: &str) -> Result<Vec<IpAddr>, DownloaderError> {
+        if let Ok(ip) = hostname.trim_matches(|c| c == '[' || c == ']').parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+
+        let addresses: Vec<IpAddr> = self
+            .resolver()?
+            .lookup_ip(hostname)
+            .map_err(|e| DownloaderError::DnsError(format!("{}: {}", hostname, e)))?
+            .iter()
+            .collect();
+        if addresses.is_empty() {
+            return Err(DownloaderError::DnsError(format!("No IP address found for {}", hostname)));
+        }
+        Ok(addresses)
+    }
+
+    /// Resolves `hostname` like [`Self::lookup`], orders the addresses with
+    /// [`interleave_families`] and rotates them so that successive calls start with
+    /// successive addresses.
+    ///
+    /// Used to spread the connections of a download over all of a server's addresses.
+    ///
+    /// # Errors
+    /// Returns `DownloaderError::DnsError` if the lookup fails.
+    pub fn lookup_round_robin(&self, hostname: &str) -> Result<Vec<IpAddr>, DownloaderError> {
+        let mut addresses = interleave_families(&self.lookup(hostname)?);
+        let turn = self.turn.fetch_add(1, Ordering::Relaxed);
+        let len = addresses.len();
+        addresses.rotate_left(turn % len);
+        Ok(addresses)
+    }
+
+    fn resolver(&self) -> Result<Arc<TrustResolver>, DownloaderError> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(resolver) = inner.as_ref() {
+            return Ok(Arc::clone(resolver));
+        }
+
+        let (config, options) = resolver_config(&self.options)?;
+        let resolver = Arc::new(
+            TrustResolver::new(config, options).map_err(|e| DownloaderError::DnsError(e.to_string()))?,
+        );
+        *inner = Some(Arc::clone(&resolver));
+        Ok(resolver)
+    }
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new(DnsOptions::default())
+    }
+}
+
+/// Orders addresses for connection attempts as described in RFC 8305: IPv6 first, then
+/// alternating between IPv6 and IPv4, keeping the order within each family.
+pub fn interleave_families(addresses: &[IpAddr]) -> Vec<IpAddr> {
+    let (mut v6, mut v4): (Vec<IpAddr>, Vec<IpAddr>) =
+        addresses.iter().partition(|address| address.is_ipv6());
+    v6.reverse();
+    v4.reverse();
+
+    let mut ordered = Vec::with_capacity(addresses.len());
+    while !v6.is_empty() || !v4.is_empty() {
+        ordered.extend(v6.pop());
+        ordered.extend(v4.pop());
+    }
+    ordered
+}
+
+/// Builds the `trust-dns-resolver` configuration for `options`.
+fn resolver_config(options: &DnsOptions) -> Result<(ResolverConfig, ResolverOpts), DownloaderError> {
+    let (config, opts) = match &options.resolv_conf {
+        Some(path) => {
+            let data = fs::read(path).map_err(|e| {
+                DownloaderError::DnsError(format!("Cannot read {}: {}", path.display(), e))
+            })?;
+            parse_resolv_conf(&data)?
+        }
+        None => system_conf()?,
+    };
+
+    let nameservers: Vec<SocketAddr> = if options.nameservers.is_empty() {
+        let mut addresses: Vec<SocketAddr> = Vec::new();
+        for server in config.name_servers() {
+            if !addresses.contains(&server.socket_addr) {
+                addresses.push(server.socket_addr);
+            }
+        }
+        addresses
+    } else {
+        options.nameservers.clone()
+    };
+    if nameservers.is_empty() {
+        return Err(DownloaderError::DnsError("No nameservers configured".into()));
+    }
+
+    let protocols: &[Protocol] = if options.use_tcp {
+        &[Protocol::Tcp]
+    } else {
+        &[Protocol::Udp, Protocol::Tcp]
+    };
+    let mut servers = Vec::new();
+    for address in nameservers {
+        for protocol in protocols {
+            servers.push(NameServerConfig::new(address, *protocol));
+        }
+    }
+
+    let config = ResolverConfig::from_parts(config.domain().cloned(), config.search().to_vec(), servers);
+    Ok((config, opts))
+}
+
+#[cfg(unix)]
+fn parse_resolv_conf(data: &[u8]) -> Result<(ResolverConfig, ResolverOpts), DownloaderError> {
+    trust_dns_resolver::system_conf::parse_resolv_conf(data)
+        .map_err(|e| DownloaderError::DnsError(e.to_string()))
+}
+
+#[cfg(not(unix))]
+fn parse_resolv_conf(_data: &[u8]) -> Result<(ResolverConfig, ResolverOpts), DownloaderError> {
+    Err(DownloaderError::DnsError("resolv.conf files are only supported on Unix".into()))
+}
+
+fn system_conf() -> Result<(ResolverConfig, ResolverOpts), DownloaderError> {
+    trust_dns_resolver::system_conf::read_system_conf()
+        .map_err(|e| DownloaderError::DnsError(format!("Cannot read the system DNS configuration: {}", e)))
+}
+
 /// Resolves the given hostname to an IP address.
 ///
+/// Uses a process-wide `Resolver` with the system configuration.
+///
 /// # Parameters
 /// - `hostname`: The hostname to resolve.
 ///
 /// # Returns
-/// The resolved IP address as an `IpAddr`.
+/// The first resolved IP address as an `IpAddr`.
 pub fn get_request_ip(hostname: &str) -> Result<IpAddr, DownloaderError> {
-    if let Ok(ip) = hostname.parse::<IpAddr>() {
-        return Ok(ip);
-    }
-
-    let socket_addr = format!("{}:443", hostname)
-        .to_socket_addrs()
-        .map_err(|e| DownloaderError::DnsError(e.to_string()))?
-        .next()
-        .ok_or_else(|| DownloaderError::DnsError("No IP address found".into()))?;
-    Ok(socket_addr.ip())
+    static SYSTEM: OnceLock<Resolver> = OnceLock::new();
+    let addresses = SYSTEM.get_or_init(Resolver::default).lookup(hostname)?;
+    Ok(addresses[0])
 }
 
 #[cfg(test)]
 mod tests {
-    use super::get_request_ip;
-    use std::net::IpAddr;
+    use super::{get_request_ip, interleave_families, resolver_config, DnsOptions, Resolver};
+    use crate::error::DownloaderError;
+    use std::io::{Read, Write};
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use trust_dns_resolver::config::Protocol;
 
     #[test]
     fn test_valid_hostname() {
@@ -46,4 +238,150 @@ mod tests {
         let result = get_request_ip("invalid-hostname");
         assert!(result.is_err());
     }
+
+    /// Answers a DNS query with one `A` record for 10.1.2.3 (TTL 300) if it asks for
+    /// `A`, and with an empty answer otherwise.
+    fn dns_answer(query: &[u8]) -> Vec<u8> {
+        let mut end = 12;
+        while query[end] != 0 {
+            end += query[end] as usize + 1;
+        }
+        let question = &query[12..end + 5];
+        let is_a = query[end + 1..end + 3] == [0, 1];
+
+        let mut answer = vec![query[0], query[1], 0x81, 0x80, 0, 1, 0, is_a as u8, 0, 0, 0, 0];
+        answer.extend_from_slice(question);
+        if is_a {
+            answer.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 1, 0x2c, 0, 4, 10, 1, 2, 3]);
+        }
+        answer
+    }
+
+    #[test]
+    fn test_lookup_uses_configured_nameserver_and_caches() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let nameserver = socket.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&queries);
+        thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            while let Ok((n, peer)) = socket.recv_from(&mut buffer) {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let _ = socket.send_to(&dns_answer(&buffer[..n]), peer);
+            }
+        });
+
+        let resolver = Resolver::new(DnsOptions {
+            nameservers: vec![nameserver],
+            ..DnsOptions::default()
+        });
+        let expected = vec![IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3))];
+        assert_eq!(resolver.lookup("files.example.test").unwrap(), expected);
+        let after_first = queries.load(Ordering::SeqCst);
+        assert!(after_first > 0);
+
+        // Further lookups, from any thread, are answered from the cache.
+        let resolver = Arc::new(resolver);
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let resolver = Arc::clone(&resolver);
+                thread::spawn(move || resolver.lookup("files.example.test").unwrap())
+            })
+            .collect();
+        for worker in workers {
+            assert_eq!(worker.join().unwrap(), expected);
+        }
+        assert_eq!(queries.load(Ordering::SeqCst), after_first);
+    }
+
+    #[test]
+    fn test_lookup_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let nameserver = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for socket in listener.incoming() {
+                let mut socket = socket.unwrap();
+                loop {
+                    let mut length = [0u8; 2];
+                    if socket.read_exact(&mut length).is_err() {
+                        break;
+                    }
+                    let mut query = vec![0u8; u16::from_be_bytes(length) as usize];
+                    socket.read_exact(&mut query).unwrap();
+                    let answer = dns_answer(&query);
+                    socket.write_all(&(answer.len() as u16).to_be_bytes()).unwrap();
+                    socket.write_all(&answer).unwrap();
+                }
+            }
+        });
+
+        let resolver = Resolver::new(DnsOptions {
+            nameservers: vec![nameserver],
+            use_tcp: true,
+            ..DnsOptions::default()
+        });
+        assert_eq!(
+            resolver.lookup("files.example.test").unwrap(),
+            vec![IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3))]
+        );
+    }
+
+    #[test]
+    fn test_resolv_conf_file() {
+        let dir = std::env::temp_dir().join(format!("pd-dns-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("resolv.conf");
+        std::fs::write(&path, "nameserver 192.0.2.53\nsearch corp.example\noptions timeout:1\n").unwrap();
+
+        let options = DnsOptions { resolv_conf: Some(path), use_tcp: true, ..DnsOptions::default() };
+        let (config, opts) = resolver_config(&options).unwrap();
+        let servers: Vec<_> = config.name_servers().iter().map(|s| (s.socket_addr, s.protocol)).collect();
+        assert_eq!(servers, vec![(SocketAddr::from(([192, 0, 2, 53], 53)), Protocol::Tcp)]);
+        assert_eq!(config.search().len(), 1);
+        assert_eq!(opts.timeout, Duration::from_secs(1));
+
+        let missing = Resolver::new(DnsOptions {
+            resolv_conf: Some(dir.join("missing.conf")),
+            ..DnsOptions::default()
+        });
+        assert!(matches!(missing.lookup("files.example.test"), Err(DownloaderError::DnsError(_))));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_interleave_families() {
+        let addresses: Vec<IpAddr> = ["10.0.0.1", "10.0.0.2", "10.0.0.3", "2001:db8::1", "2001:db8::2"]
+            .iter()
+            .map(|a| a.parse().unwrap())
+            .collect();
+        let ordered: Vec<String> = interleave_families(&addresses).iter().map(|a| a.to_string()).collect();
+        assert_eq!(ordered, ["2001:db8::1", "10.0.0.1", "2001:db8::2", "10.0.0.2", "10.0.0.3"]);
+    }
+
+    #[test]
+    fn test_lookup_round_robin() {
+        let resolver = Resolver::default();
+        assert_eq!(resolver.lookup_round_robin("127.0.0.1").unwrap(), vec![IpAddr::from([127, 0, 0, 1])]);
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let nameserver = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            while let Ok((n, peer)) = socket.recv_from(&mut buffer) {
+                // Two A records: 10.1.2.3 and 10.1.2.4.
+                let mut answer = dns_answer(&buffer[..n]);
+                if answer[7] == 1 {
+                    answer[7] = 2;
+                    answer.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 1, 0x2c, 0, 4, 10, 1, 2, 4]);
+                }
+                let _ = socket.send_to(&answer, peer);
+            }
+        });
+        let resolver = Resolver::new(DnsOptions { nameservers: vec![nameserver], ..DnsOptions::default() });
+        let first = resolver.lookup_round_robin("files.example.test").unwrap();
+        let second = resolver.lookup_round_robin("files.example.test").unwrap();
+        assert_eq!(first.len(), 2);
+        assert_eq!(first[0], second[1]);
+        assert_eq!(first[1], second[0]);
+    }
 }
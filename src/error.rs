@@ -4,7 +4,7 @@
 //! errors that might occur during the download process.
 //!
 //! ## Features
-//! - Handles IO, TLS, URL, DNS, and connection errors.
+//! - Handles IO, TLS, URL, DNS, connection, proxy, timeout, HTTP status, redirect, resume and checksum errors.
 
 use std::io;
 use url;
@@ -22,8 +22,15 @@ pub enum DownloaderError {
     DnsError(String),
     FileError(String),
     ConnectionError(String),
+    ProxyError(String),
     ResponseError(String),
     UserInputError(String),
+    ResumeError(String),
+    ChecksumError(String),
+    HttpStatus(u16, String),
+    RedirectError(String),
+    Timeout(String),
+    ThreadError(String),
 }
 
 impl fmt::Display for DownloaderError {
@@ -35,9 +42,16 @@ impl fmt::Display for DownloaderError {
             DownloaderError::UrlParseError(e) => write!(f, "URL parse error: {}", e),
             DownloaderError::DnsError(e) => write!(f, "DNS error: {}", e),
             DownloaderError::ConnectionError(e) => write!(f, "Connection error: {}", e),
+            DownloaderError::ProxyError(e) => write!(f, "Proxy error: {}", e),
             DownloaderError::ResponseError(e) => write!(f, "Server response error: {}", e),
             DownloaderError::UserInputError(e) => write!(f, "Invalid input: {}", e),
             DownloaderError::FileError(e) => write!(f, "File error: {}", e),
+            DownloaderError::ResumeError(e) => write!(f, "Cannot resume download: {}", e),
+            DownloaderError::ChecksumError(e) => write!(f, "Checksum mismatch: {}", e),
+            DownloaderError::HttpStatus(code, reason) => write!(f, "HTTP error: {} {}", code, reason),
+            DownloaderError::RedirectError(e) => write!(f, "Redirect error: {}", e),
+            DownloaderError::Timeout(e) => write!(f, "Timed out: {}", e),
+            DownloaderError::ThreadError(e) => write!(f, "Worker thread error: {}", e),
         }
     }
 }
@@ -45,8 +59,15 @@ impl fmt::Display for DownloaderError {
 impl std::error::Error for DownloaderError {}
 
 impl From<io::Error> for DownloaderError {
+    /// Socket timeouts become `DownloaderError::Timeout`; other errors are kept as they are.
     fn from(error: io::Error) -> Self {
-        DownloaderError::IoError(error)
+        match error.kind() {
+            io::ErrorKind::WouldBlock => {
+                DownloaderError::Timeout("no data received within the read timeout".into())
+            }
+            io::ErrorKind::TimedOut => DownloaderError::Timeout(error.to_string()),
+            _ => DownloaderError::IoError(error),
+        }
     }
 }
 
@@ -0,0 +1,319 @@
+//! # Output Filenames
+//!
+//! This module suggests a name for the downloaded file when the user gives none. The
+//! server's `Content-Disposition` header wins, then the last segment of the URL path, and
+//! a name without an extension gets one from the `Content-Type`. Names that come from the
+//! server or the URL are sanitized, so that they always name a file in the current
+//! directory.
+//!
+//! ## Features
+//! - Parses `Content-Disposition`, including RFC 5987 `filename*=UTF-8''...` values.
+//! - Maps common `Content-Type` values to file extensions.
+//! - Strips directories, control characters and reserved names from suggested names.
+
+use std::path::Path;
+use url::Url;
+use crate::http::ResourceInfo;
+use crate::util::{percent_decode, percent_decode_bytes};
+
+/// The name used when neither the server nor the URL suggests one.
+pub const FALLBACK_FILENAME: &str = "downloaded_file";
+
+/// Longest filename, in bytes, that common filesystems accept.
+const MAX_FILENAME_LEN: usize = 255;
+
+/// Suggests a filename for the file described by `info`.
+///
+/// # Parameters
+/// - `url`: The URL the download was started with. Its last path segment names the file
+///   when the server suggests no name; the URL after redirects is used if it has none.
+/// - `info`: The metadata returned by the probe.
+///
+/// # Returns
+/// A sanitized filename without directories, `downloaded_file` if nothing suggests one.
+pub fn suggested_filename(url: &Url, info: &ResourceInfo) -> String {
+    let name = info
+        .filename
+        .as_deref()
+        .and_then(sanitize)
+        .or_else(|| url_filename(url))
+        .or_else(|| url_filename(&info.url))
+        .unwrap_or_else(|| FALLBACK_FILENAME.to_string());
+    with_extension(name, info.content_type.as_deref())
+}
+
+/// Returns the sanitized, percent-decoded last path segment of `url`, if it has one.
+pub fn url_filename(url: &Url) -> Option<String> {
+    let segment = url.path_segments()?.next_back()?;
+    sanitize(&percent_decode(segment))
+}
+
+/// Appends the extension for `content_type` to `name`, unless it already has one or the
+/// type has no known extension.
+pub fn with_extension(name: String, content_type: Option<&str>) -> String {
+    if Path::new(&name).extension().is_some() {
+        return name;
+    }
+    match content_type.and_then(extension_for) {
+        Some(extension) => format!("{}.{}", name, extension),
+        None => name,
+    }
+}
+
+/// Extracts the filename from a `Content-Disposition` header value.
+///
+/// An RFC 5987 `filename*` parameter in UTF-8 or ISO-8859-1 takes precedence over a
+/// plain `filename`. The name is returned as sent; pass it through [`sanitize`] before
+/// using it.
+///
+/// # Returns
+/// The filename, or `None` if the header has no usable filename parameter.
+pub fn parse_content_disposition(value: &str) -> Option<String> {
+    let mut filename = None;
+    let mut extended = None;
+    for (name, value) in parameters(value) {
+        match name.to_ascii_lowercase().as_str() {
+            "filename" if filename.is_none() => filename = Some(value),
+            "filename*" if extended.is_none() => extended = decode_extended(&value),
+            _ => {}
+        }
+    }
+    extended.or(filename).filter(|name| !name.is_empty())
+}
+
+/// Splits the parameters of a header value like `attachment; name="value"; x=y` into
+/// name/value pairs, unquoting quoted values. The leading disposition type is skipped.
+fn parameters(value: &str) -> Vec<(String, String)> {
+    let mut parameters = Vec::new();
+    let mut chars = value.chars().peekable();
+    // Skip the disposition type.
+    chars.by_ref().take_while(|&c| c != ';').for_each(drop);
+    while chars.peek().is_some() {
+        let mut name = String::new();
+        let mut has_value = false;
+        for c in chars.by_ref() {
+            match c {
+                '=' => has_value = true,
+                ';' => {}
+                c => {
+                    name.push(c);
+                    continue;
+                }
+            }
+            break;
+        }
+        let mut value = String::new();
+        if !has_value {
+            parameters.push((name.trim().to_string(), value));
+            continue;
+        }
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => value.extend(chars.next()),
+                    c => value.push(c),
+                }
+            }
+            chars.by_ref().take_while(|&c| c != ';').for_each(drop);
+        } else {
+            value = chars.by_ref().take_while(|&c| c != ';').collect();
+        }
+        parameters.push((name.trim().to_string(), value.trim().to_string()));
+    }
+    parameters
+}
+
+/// Decodes an RFC 5987 value, `charset'language'percent-encoded`.
+fn decode_extended(value: &str) -> Option<String> {
+    let mut fields = value.splitn(3, '\'');
+    let (charset, _language, encoded) = (fields.next()?, fields.next()?, fields.next()?);
+    let bytes = percent_decode_bytes(encoded);
+    match charset.to_ascii_lowercase().as_str() {
+        "utf-8" => String::from_utf8(bytes).ok(),
+        "iso-8859-1" => Some(bytes.iter().map(|&byte| byte as char).collect()),
+        _ => None,
+    }
+}
+
+/// Maps a `Content-Type` value to a file extension, ignoring its parameters.
+///
+/// # Returns
+/// The extension without a dot, or `None` for unknown and generic types such as
+/// `application/octet-stream`.
+pub fn extension_for(content_type: &str) -> Option<&'static str> {
+    let mime = content_type.split(';').next()?.trim().to_ascii_lowercase();
+    let extension = match mime.as_str() {
+        "application/pdf" => "pdf",
+        "application/zip" | "application/x-zip-compressed" => "zip",
+        "application/gzip" | "application/x-gzip" => "gz",
+        "application/x-tar" => "tar",
+        "application/x-bzip2" => "bz2",
+        "application/x-xz" => "xz",
+        "application/zstd" => "zst",
+        "application/x-7z-compressed" => "7z",
+        "application/vnd.rar" | "application/x-rar-compressed" => "rar",
+        "application/java-archive" => "jar",
+        "application/vnd.debian.binary-package" => "deb",
+        "application/x-rpm" => "rpm",
+        "application/x-iso9660-image" => "iso",
+        "application/vnd.android.package-archive" => "apk",
+        "application/x-msdownload" => "exe",
+        "application/wasm" => "wasm",
+        "application/json" => "json",
+        "application/xml" | "text/xml" => "xml",
+        "application/javascript" | "text/javascript" => "js",
+        "application/msword" => "doc",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => "docx",
+        "application/vnd.ms-excel" => "xls",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" => "xlsx",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation" => "pptx",
+        "text/plain" => "txt",
+        "text/html" => "html",
+        "text/css" => "css",
+        "text/csv" => "csv",
+        "text/markdown" => "md",
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/svg+xml" => "svg",
+        "image/bmp" => "bmp",
+        "image/tiff" => "tif",
+        "image/x-icon" | "image/vnd.microsoft.icon" => "ico",
+        "audio/mpeg" => "mp3",
+        "audio/ogg" => "ogg",
+        "audio/wav" | "audio/x-wav" => "wav",
+        "audio/flac" => "flac",
+        "video/mp4" => "mp4",
+        "video/webm" => "webm",
+        "video/x-matroska" => "mkv",
+        "video/quicktime" => "mov",
+        _ => return None,
+    };
+    Some(extension)
+}
+
+/// Turns a name suggested by a server or URL into a safe filename.
+///
+/// Only the part after the last `/` or `\` is kept. Control characters are removed,
+/// characters Windows forbids in filenames are replaced by `_`, leading dots (hidden
+/// files) and trailing dots and spaces are trimmed, reserved Windows device names get a
+/// `_` prefix, and names longer than 255 bytes are shortened, keeping the extension.
+///
+/// # Returns
+/// The sanitized name, or `None` if nothing usable is left.
+pub fn sanitize(name: &str) -> Option<String> {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = base
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| if "<>:\"|?*".contains(c) { '_' } else { c })
+        .collect();
+    let mut cleaned = cleaned
+        .trim_start_matches(|c: char| c == '.' || c.is_whitespace())
+        .trim_end_matches(|c: char| c == '.' || c.is_whitespace())
+        .to_string();
+    if cleaned.is_empty() {
+        return None;
+    }
+
+    const RESERVED: [&str; 22] = [
+        "con", "prn", "aux", "nul", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8", "com9", "lpt1",
+        "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+    ];
+    let stem = cleaned.split('.').next().unwrap_or_default().trim_end().to_ascii_lowercase();
+    if RESERVED.contains(&stem.as_str()) {
+        cleaned.insert(0, '_');
+    }
+
+    if cleaned.len() > MAX_FILENAME_LEN {
+        let extension = match cleaned.rfind('.') {
+            Some(dot) if cleaned.len() - dot <= 16 => cleaned[dot..].to_string(),
+            _ => String::new(),
+        };
+        let mut end = MAX_FILENAME_LEN - extension.len();
+        while !cleaned.is_char_boundary(end) {
+            end -= 1;
+        }
+        cleaned = format!("{}{}", &cleaned[..end], extension);
+    }
+    Some(cleaned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extension_for, parse_content_disposition, sanitize, suggested_filename, url_filename};
+    use crate::http::ResourceInfo;
+    use url::Url;
+
+    fn info(url: &str, filename: Option<&str>, content_type: Option<&str>) -> ResourceInfo {
+        ResourceInfo {
+            url: Url::parse(url).unwrap(),
+            supports_range: true,
+            total_size: Some(1),
+            etag: None,
+            last_modified: None,
+            filename: filename.map(str::to_string),
+            content_type: content_type.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_parse_content_disposition() {
+        let parse = parse_content_disposition;
+        assert_eq!(parse("attachment; filename=report.pdf").as_deref(), Some("report.pdf"));
+        assert_eq!(parse("attachment; filename=\"my \\\"big\\\" file.zip\"").as_deref(), Some("my \"big\" file.zip"));
+        assert_eq!(
+            parse("attachment; filename=\"fallback.txt\"; filename*=UTF-8''na%C3%AFve%20r%C3%A9sum%C3%A9.txt").as_deref(),
+            Some("naïve résumé.txt")
+        );
+        assert_eq!(parse("attachment; FILENAME*=iso-8859-1'en'caf%E9.txt").as_deref(), Some("café.txt"));
+        assert_eq!(parse("attachment; filename*=KOI8-R''x.txt; filename=y.txt").as_deref(), Some("y.txt"));
+        assert_eq!(parse("inline; name=\"a; b\"; filename = a.bin").as_deref(), Some("a.bin"));
+        assert_eq!(parse("inline"), None);
+        assert_eq!(parse("attachment; filename=\"\""), None);
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("report.pdf").as_deref(), Some("report.pdf"));
+        assert_eq!(sanitize("../../etc/passwd").as_deref(), Some("passwd"));
+        assert_eq!(sanitize("C:\\Windows\\evil.exe").as_deref(), Some("evil.exe"));
+        assert_eq!(sanitize("a\u{7}b\r\n.txt").as_deref(), Some("ab.txt"));
+        assert_eq!(sanitize("what?.txt").as_deref(), Some("what_.txt"));
+        assert_eq!(sanitize(".bashrc").as_deref(), Some("bashrc"));
+        assert_eq!(sanitize("CON.txt").as_deref(), Some("_CON.txt"));
+        assert_eq!(sanitize(".."), None);
+        assert_eq!(sanitize("dir/"), None);
+        let long = format!("{}.tar.gz", "é".repeat(200));
+        let shortened = sanitize(&long).unwrap();
+        assert!(shortened.len() <= 255 && shortened.ends_with(".gz"));
+    }
+
+    #[test]
+    fn test_extension_for() {
+        assert_eq!(extension_for("application/pdf"), Some("pdf"));
+        assert_eq!(extension_for("Text/HTML; charset=utf-8"), Some("html"));
+        assert_eq!(extension_for("application/octet-stream"), None);
+    }
+
+    #[test]
+    fn test_suggested_filename() {
+        let url = Url::parse("https://example.com/download?id=123").unwrap();
+        let suggest = |info: &ResourceInfo| suggested_filename(&url, info);
+        assert_eq!(suggest(&info(url.as_str(), Some("report.pdf"), None)), "report.pdf");
+        assert_eq!(suggest(&info(url.as_str(), None, Some("application/pdf"))), "download.pdf");
+        assert_eq!(suggest(&info(url.as_str(), Some("../secret"), Some("text/plain"))), "secret.txt");
+        assert_eq!(suggest(&info(url.as_str(), None, Some("application/octet-stream"))), "download");
+
+        let root = Url::parse("https://example.com/").unwrap();
+        assert_eq!(suggested_filename(&root, &info("https://cdn.example.com/a.iso", None, None)), "a.iso");
+        assert_eq!(suggested_filename(&root, &info(root.as_str(), None, None)), "downloaded_file");
+        assert_eq!(url_filename(&Url::parse("https://example.com/my%20file.bin").unwrap()).as_deref(), Some("my file.bin"));
+    }
+}
@@ -5,81 +5,311 @@
 //!
 //! ## Features
 //! - Sends `HEAD` requests to check file details.
-//! - Parses response headers for content-length and range support.
+//! - Reads content-length, range support, validators and the suggested filename from the
+//!   response headers.
+//! - Sends ranged and whole-file `GET` requests.
+//! - Follows redirects up to a configurable number of hops, refusing HTTPS-to-HTTP downgrades.
+//! - Requests the full path and query of a URL, so presigned and tokenized URLs work.
+//! - Sends the configured credentials to the origin of the download, and only there.
+//! - Sends the configured `User-Agent`, `Referer` and extra headers, and the cookies of
+//!   the cookie jar, which is updated from every response.
 
 use std::io::{Read, Write};
-use native_tls::TlsStream;
-use std::net::TcpStream;
+use url::{Position, Url};
+use crate::auth;
+use crate::body;
+use crate::config::DownloadConfig;
+use crate::connection::{self, Stream};
 use crate::error::DownloaderError;
+use crate::filename;
+use crate::response::{self, Response};
 
-/// Sends a `HEAD` request to the specified path and retrieves metadata.
+/// Metadata about the remote file, as reported by a `HEAD` request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResourceInfo {
+    /// The URL the metadata was read from, after following any redirects.
+    pub url: Url,
+    /// Whether the server accepts byte range requests.
+    pub supports_range: bool,
+    /// The total size of the file, if the server sent a `Content-Length`.
+    pub total_size: Option<u64>,
+    /// The `ETag` validator, if the server sent one.
+    pub etag: Option<String>,
+    /// The `Last-Modified` validator, if the server sent one.
+    pub last_modified: Option<String>,
+    /// The filename from the `Content-Disposition` header, unsanitized; see
+    /// [`filename::suggested_filename`].
+    pub filename: Option<String>,
+    /// The `Content-Type` header, if the server sent one.
+    pub content_type: Option<String>,
+}
+
+/// Probes `url` with `HEAD` requests, following redirects, and retrieves metadata.
 ///
 /// # Parameters
-/// - `stream`: The TLS stream to the server.
-/// - `hostname`: The hostname of the server.
-/// - `path`: The file path on the server.
+/// - `url`: The URL of the file.
+/// - `config`: The download configuration, which limits how redirects are followed.
 ///
 /// # Returns
-/// A tuple containing:
-/// - A boolean indicating if range requests are supported.
-/// - The total size of the file.
+/// A `ResourceInfo` describing the file at the final URL.
 ///
 /// # Errors
-/// Returns a `DownloaderError` if the request fails or the response is invalid.
+/// Returns a `DownloaderError` if a connection or request fails, a redirect is refused
+/// (see [`redirect_target`]), the final status is not `2xx` or the response is invalid.
+pub fn probe(url: &Url, config: &DownloadConfig) -> Result<ResourceInfo, DownloaderError> {
+    let mut current = url.clone();
+    let mut hops = 0;
+    loop {
+        let mut stream = connection::connect(&current, config)?;
+        let response = head(&mut stream, &current, &request_headers(config, &current)?)?;
+        store_cookies(config, &current, &response);
+        match redirect_target(&current, &response, config, hops)? {
+            Some(next) => {
+                current = next;
+                hops += 1;
+            }
+            None => return resource_info(current, &response),
+        }
+    }
+}
+
+/// Sends a `GET` request for `url`, following redirects, and reads the response head.
+///
+/// A byte range is requested without content coding so that its offsets stay valid;
+/// whole-file requests accept the codings [`body::content_reader`] can undo.
+///
+/// # Parameters
+/// - `url`: The URL of the file.
+/// - `range`: The first and last byte to request, or `None` for the whole file.
+/// - `config`: The download configuration, which limits how redirects are followed.
+///
+/// # Returns
+/// The final response, the body bytes read together with its head, and the connection
+/// the rest of the body is read from.
+///
+/// # Errors
+/// Returns a `DownloaderError` if a connection or request fails or a redirect is refused.
+/// The status of the final response is not checked.
+pub fn get(
+    url: &Url,
+    range: Option<(u64, u64)>,
+    config: &DownloadConfig,
+) -> Result<(Response, Vec<u8>, Stream), DownloaderError> {
+    let mut current = url.clone();
+    let mut hops = 0;
+    loop {
+        let mut stream = connection::connect(&current, config)?;
+
+        let (range_header, accept_encoding) = match range {
+            Some((start, end)) => (format!("Range: bytes={}-{}\r\n", start, end), body::IDENTITY_ENCODING),
+            None => (String::new(), body::ACCEPTED_ENCODINGS),
+        };
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\n{}Accept-Encoding: {}\r\nConnection: close\r\n{}\r\n",
+            request_target(&current), host_header(&current), range_header, accept_encoding, request_headers(config, &current)?
+        );
+
+        stream.write_all(request.as_bytes())?;
 
-pub fn send_head_request(
-    stream: &mut TlsStream<TcpStream>,
-    hostname: &str,
-    path: &str,
-) -> Result<(bool, u64), DownloaderError> {
+        let (response, body_prefix) = response::read_response_head(&mut stream)?;
+        store_cookies(config, &current, &response);
+        match redirect_target(&current, &response, config, hops)? {
+            Some(next) => {
+                current = next;
+                hops += 1;
+            }
+            None => return Ok((response, body_prefix, stream)),
+        }
+    }
+}
+
+/// Sends a single `HEAD` request for `url` and retrieves metadata.
+///
+/// The request carries the same headers, credentials and cookies as every other request
+/// of the download. Redirects are not followed; use [`probe`] for that.
+///
+/// # Parameters
+/// - `stream`: The connection to the server, plain or TLS.
+/// - `url`: The URL of the file.
+/// - `config`: The download configuration, with the headers, credentials and cookie jar.
+///
+/// # Returns
+/// A `ResourceInfo` with range support, file size and validators.
+///
+/// # Errors
+/// Returns a `DownloaderError` if the request fails, the status is not `2xx` or the
+/// response is invalid.
+pub fn send_head_request<S: Read + Write>(
+    stream: &mut S,
+    url: &Url,
+    config: &DownloadConfig,
+) -> Result<ResourceInfo, DownloaderError> {
+    let response = head(stream, url, &request_headers(config, url)?)?;
+    store_cookies(config, url, &response);
+    resource_info(url.clone(), &response)
+}
+
+/// Decides whether `response` redirects the request for `current`, and where to.
+///
+/// Relative `Location` values are resolved against `current`.
+///
+/// # Parameters
+/// - `current`: The URL that was requested.
+/// - `response`: The response to that request.
+/// - `config`: The download configuration, with the hop limit and downgrade policy.
+/// - `hops`: The number of redirects already followed.
+///
+/// # Returns
+/// The URL to request next, or `None` if the response is not a redirect.
+///
+/// # Errors
+/// Returns `DownloaderError::RedirectError` if the hop limit is reached, the target is
+/// not an HTTP(S) URL, or it would downgrade HTTPS to HTTP without
+/// `allow_insecure_redirects`.
+pub fn redirect_target(
+    current: &Url,
+    response: &Response,
+    config: &DownloadConfig,
+    hops: usize,
+) -> Result<Option<Url>, DownloaderError> {
+    if !matches!(response.status, 301 | 302 | 303 | 307 | 308) {
+        return Ok(None);
+    }
+    let location = response.headers.get("location").ok_or_else(|| {
+        DownloaderError::ResponseError(format!("{} redirect without a Location header", response.status))
+    })?;
+    if hops >= config.max_redirects {
+        return Err(DownloaderError::RedirectError(format!(
+            "Too many redirects (limit is {})",
+            config.max_redirects
+        )));
+    }
+
+    let next = current.join(location)?;
+    if !matches!(next.scheme(), "http" | "https") {
+        return Err(DownloaderError::RedirectError(format!(
+            "Unsupported redirect target: {}",
+            auth::redact(next.as_str())
+        )));
+    }
+    if current.scheme() == "https" && next.scheme() == "http" && !config.allow_insecure_redirects {
+        return Err(DownloaderError::RedirectError(format!(
+            "Refusing to follow redirect from HTTPS to HTTP: {}",
+            auth::redact(next.as_str())
+        )));
+    }
+    Ok(Some(next))
+}
+
+/// Sends a `HEAD` request for `url` with the further header lines `headers`.
+fn head<S: Read + Write>(stream: &mut S, url: &Url, headers: &str) -> Result<Response, DownloaderError> {
     let request = format!(
-        "HEAD {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nUser-Agent: rust-downloader/1.0\r\n\r\n",
-        path, hostname
+        "HEAD {} HTTP/1.1\r\nHost: {}\r\nAccept-Encoding: {}\r\nConnection: close\r\n{}\r\n",
+        request_target(url), host_header(url), body::IDENTITY_ENCODING, headers
     );
 
     stream.write_all(request.as_bytes())?;
 
-    let mut response = String::new();
-    stream.read_to_string(&mut response)?;
+    let (response, _) = response::read_response_head(stream)?;
+    Ok(response)
+}
+
+/// Builds the configurable header lines for a request to `url`: the configured headers,
+/// the `Authorization` line when credentials are sent there (see
+/// [`auth::Auth::header`]) and the cookies the jar has for it, together with any
+/// configured `Cookie` header.
+fn request_headers(config: &DownloadConfig, url: &Url) -> Result<String, DownloaderError> {
+    let origin = Url::parse(&config.url)?;
+    let cookies = config.cookies.as_ref().and_then(|jar| jar.header(url));
+    let mut lines = config.headers.to_lines(cookies.as_deref());
+    if let Some(value) = config.auth.header(&origin, url)? {
+        lines.push_str(&format!("Authorization: {}\r\n", value));
+    }
+    Ok(lines)
+}
 
-    let supports_range = response.contains("Accept-Ranges: bytes");
-    let content_length = parse_content_length(&response)?;
+/// Stores the `Set-Cookie` headers of `response` in the cookie jar, if there is one.
+fn store_cookies(config: &DownloadConfig, url: &Url, response: &Response) {
+    if let Some(jar) = &config.cookies {
+        jar.store(url, response.headers.get_all("set-cookie"));
+    }
+}
+
+fn resource_info(url: Url, response: &Response) -> Result<ResourceInfo, DownloaderError> {
+    if !response.is_success() {
+        return Err(DownloaderError::HttpStatus(response.status, response.reason.clone()));
+    }
 
-    Ok((supports_range, content_length))
+    Ok(ResourceInfo {
+        url,
+        supports_range: response.headers.contains_token("accept-ranges", "bytes"),
+        total_size: response.content_length()?,
+        etag: response.headers.get("etag").map(str::to_string),
+        last_modified: response.headers.get("last-modified").map(str::to_string),
+        filename: response.headers.get("content-disposition").and_then(filename::parse_content_disposition),
+        content_type: response.headers.get("content-type").map(str::to_string),
+    })
 }
 
-/// Parses the `Content-Length` header from an HTTP response.
+/// Builds the request target sent in the request line for a URL.
+///
+/// This is the path followed by the query, if any, without the fragment. `Url` already
+/// percent-encodes most characters; anything else RFC 3986 does not allow in a path or
+/// query, such as `|` or `^`, is percent-encoded here. Existing `%XX` escapes, e.g. in
+/// the signature of a presigned URL, are sent unchanged.
 ///
 /// # Parameters
-/// - `response`: The raw HTTP response as a string.
+/// - `url`: The URL being requested.
 ///
 /// # Returns
-/// The file size as a `u64`.
-///
-/// # Errors
-/// Returns a `DownloaderError` if the `Content-Length` header is missing or invalid.
-fn parse_content_length(response: &str) -> Result<u64, DownloaderError> {
-    for line in response.lines() {
-        if line.to_lowercase().starts_with("content-length:") {
-            return line
-                .split(':')
-                .nth(1)
-                .and_then(|s| s.trim().parse().ok())
-                .ok_or_else(|| {
-                    DownloaderError::ResponseError("Invalid Content-Length header".into())
-                });
+/// The origin-form request target, e.g. `/file.bin?token=abc`.
+pub fn request_target(url: &Url) -> String {
+    let target = &url[Position::BeforePath..Position::AfterQuery];
+    let mut encoded = String::with_capacity(target.len());
+    for byte in target.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@/?%".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
         }
     }
-    Err(DownloaderError::ResponseError(
-        "Content-Length header not found".into(),
-    ))
+    if encoded.is_empty() {
+        encoded.push('/');
+    }
+    encoded
+}
+
+/// Builds the `Host` header value for a URL.
+///
+/// The port is only included when it differs from the scheme's default port.
+///
+/// # Parameters
+/// - `url`: The URL being requested.
+///
+/// # Returns
+/// The host, followed by `:port` for non-default ports.
+pub fn host_header(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::send_head_request;
-    use std::net::TcpStream;
+    use super::{host_header, probe, redirect_target, request_target, send_head_request};
+    use crate::auth::Auth;
+    use crate::config::DownloadConfig;
+    use crate::cookies::CookieJar;
+    use crate::error::DownloaderError;
+    use crate::response::Response;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::Arc;
+    use std::thread;
     use native_tls::TlsConnector;
+    use url::Url;
 
     #[test]
     fn test_send_head_request_valid() {
@@ -89,7 +319,9 @@ mod tests {
         if let Ok(stream) = stream_result {
             let connector = TlsConnector::new().unwrap();
             let mut tls_stream = connector.connect("example.com", stream).unwrap();
-            let result = send_head_request(&mut tls_stream, "example.com", "/");
+            let url = Url::parse("https://example.com/").unwrap();
+            let config = DownloadConfig::new(url.to_string(), "output.html".to_string(), 1);
+            let result = send_head_request(&mut tls_stream, &url, &config);
             assert!(result.is_ok(), "Expected HEAD request to succeed");
         }
     }
@@ -99,4 +331,259 @@ mod tests {
         let stream_result = TcpStream::connect("127.0.0.1:443");
         assert!(stream_result.is_err(), "Expected TcpStream connection to fail");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_send_head_request_plain_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let n = socket.read(&mut request).unwrap();
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\nAccept-Ranges: bytes\r\nContent-Length: 1234\r\nETag: \"v1\"\r\n\r\n")
+                .unwrap();
+            String::from_utf8_lossy(&request[..n]).into_owned()
+        });
+
+        let url = Url::parse(&format!("http://127.0.0.1:{}/file.bin", port)).unwrap();
+        let mut config = DownloadConfig::new(url.to_string(), "output.bin".to_string(), 1);
+        config.headers.user_agent = "test-agent/2.0".to_string();
+        config.auth = Auth::Bearer("t0ken".to_string());
+        let jar = CookieJar::new();
+        jar.store(&url, ["session=abc"]);
+        config.cookies = Some(Arc::new(jar));
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let info = send_head_request(&mut stream, &url, &config).unwrap();
+        assert_eq!(info.url, url);
+        assert!(info.supports_range);
+        assert_eq!(info.total_size, Some(1234));
+        assert_eq!(info.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(info.last_modified, None);
+
+        let request = server.join().unwrap();
+        assert!(request.contains(&format!("Host: 127.0.0.1:{}\r\n", port)));
+        assert!(request.contains("User-Agent: test-agent/2.0\r\n"));
+        assert!(request.contains("Authorization: Bearer t0ken\r\n"));
+        assert!(request.contains("Cookie: session=abc\r\n"));
+    }
+
+    #[test]
+    fn test_request_target_keeps_query() {
+        let target = |url: &str| request_target(&Url::parse(url).unwrap());
+        assert_eq!(target("https://example.com"), "/");
+        assert_eq!(target("https://example.com/a/b.bin#part"), "/a/b.bin");
+        assert_eq!(
+            target("https://example.com/a.bin?X-Amz-Signature=ab%2Fcd&X-Amz-Expires=60#x"),
+            "/a.bin?X-Amz-Signature=ab%2Fcd&X-Amz-Expires=60"
+        );
+        assert_eq!(target("https://example.com/my file|v2^.bin?q=a b"), "/my%20file%7Cv2%5E.bin?q=a%20b");
+        assert_eq!(target("https://example.com/caf\u{e9}.bin?name=\u{e9}"), "/caf%C3%A9.bin?name=%C3%A9");
+    }
+
+    #[test]
+    fn test_probe_sends_query_string() {
+        let (port, server) = serve(vec![
+            "HTTP/1.1 302 Found\r\nLocation: /cdn/file.bin?token=a%2Bb&sig=x\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nAccept-Ranges: bytes\r\nContent-Length: 42\r\n\r\n",
+        ]);
+        let url = Url::parse(&format!("http://127.0.0.1:{}/file.bin?expires=60&id=7", port)).unwrap();
+        let config = DownloadConfig::new(url.to_string(), "output.bin".to_string(), 1);
+
+        let info = probe(&url, &config).unwrap();
+        assert_eq!(info.url.query(), Some("token=a%2Bb&sig=x"));
+        assert_eq!(
+            server.join().unwrap(),
+            vec!["HEAD /file.bin?expires=60&id=7 HTTP/1.1", "HEAD /cdn/file.bin?token=a%2Bb&sig=x HTTP/1.1"]
+        );
+    }
+
+    #[test]
+    fn test_host_header_omits_default_port() {
+        let url = Url::parse("https://example.com:443/a").unwrap();
+        assert_eq!(host_header(&url), "example.com");
+        let url = Url::parse("https://example.com:8443/a").unwrap();
+        assert_eq!(host_header(&url), "example.com:8443");
+    }
+
+    #[test]
+    fn test_send_head_request_rejects_error_status() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = socket.read(&mut request).unwrap();
+            socket
+                .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 1234\r\n\r\n")
+                .unwrap();
+        });
+
+        let url = Url::parse(&format!("http://127.0.0.1:{}/missing", port)).unwrap();
+        let config = DownloadConfig::new(url.to_string(), "missing".to_string(), 1);
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let result = send_head_request(&mut stream, &url, &config);
+        server.join().unwrap();
+        assert!(matches!(result, Err(DownloaderError::HttpStatus(404, _))));
+    }
+
+    /// Answers one connection per entry of `responses`, which may refer to the port as
+    /// `{port}`, and returns the request lines received.
+    fn serve(responses: Vec<&'static str>) -> (u16, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let mut request_lines = Vec::new();
+            for response in responses {
+                let (mut socket, _) = listener.accept().unwrap();
+                let mut request = [0u8; 1024];
+                let n = socket.read(&mut request).unwrap();
+                let request = String::from_utf8_lossy(&request[..n]).into_owned();
+                request_lines.push(request.lines().next().unwrap_or_default().to_string());
+                let response = response.replace("{port}", &port.to_string());
+                socket.write_all(response.as_bytes()).unwrap();
+            }
+            request_lines
+        });
+        (port, handle)
+    }
+
+    #[test]
+    fn test_probe_follows_redirects() {
+        let (port, server) = serve(vec![
+            "HTTP/1.1 301 Moved Permanently\r\nLocation: http://127.0.0.1:{port}/mirror/file.bin\r\n\r\n",
+            "HTTP/1.1 307 Temporary Redirect\r\nLocation: ../signed/file.bin\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nAccept-Ranges: bytes\r\nContent-Length: 42\r\n\r\n",
+        ]);
+        let url = Url::parse(&format!("http://127.0.0.1:{}/file.bin", port)).unwrap();
+        let config = DownloadConfig::new(url.to_string(), "output.bin".to_string(), 1);
+
+        let info = probe(&url, &config).unwrap();
+        assert_eq!(info.url.as_str(), format!("http://127.0.0.1:{}/signed/file.bin", port));
+        assert_eq!(info.total_size, Some(42));
+        assert_eq!(
+            server.join().unwrap(),
+            vec!["HEAD /file.bin HTTP/1.1", "HEAD /mirror/file.bin HTTP/1.1", "HEAD /signed/file.bin HTTP/1.1"]
+        );
+    }
+
+    #[test]
+    fn test_probe_reads_suggested_filename() {
+        let (port, server) = serve(vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 42\r\nContent-Type: application/pdf\r\n\
+             Content-Disposition: attachment; filename*=UTF-8''r%C3%A9sum%C3%A9.pdf\r\n\r\n",
+        ]);
+        let url = Url::parse(&format!("http://127.0.0.1:{}/download?id=123", port)).unwrap();
+        let config = DownloadConfig::new(url.to_string(), "output.bin".to_string(), 1);
+
+        let info = probe(&url, &config).unwrap();
+        server.join().unwrap();
+        assert_eq!(info.filename.as_deref(), Some("résumé.pdf"));
+        assert_eq!(info.content_type.as_deref(), Some("application/pdf"));
+    }
+
+    #[test]
+    fn test_credentials_not_sent_after_cross_origin_redirect() {
+        let other = TcpListener::bind("127.0.0.1:0").unwrap();
+        let other_port = other.local_addr().unwrap().port();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for (listener, response) in [
+                (&listener, format!("HTTP/1.1 302 Found\r\nLocation: http://127.0.0.1:{}/file.bin\r\n\r\n", other_port)),
+                (&other, "HTTP/1.1 200 OK\r\nContent-Length: 42\r\n\r\n".to_string()),
+            ] {
+                let (mut socket, _) = listener.accept().unwrap();
+                let mut request = [0u8; 1024];
+                let n = socket.read(&mut request).unwrap();
+                requests.push(String::from_utf8_lossy(&request[..n]).into_owned());
+                socket.write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+
+        let url = Url::parse(&format!("http://127.0.0.1:{}/file.bin", port)).unwrap();
+        let mut config = DownloadConfig::new(url.to_string(), "output.bin".to_string(), 1);
+        config.auth = Auth::Bearer("t0ken".into());
+        probe(&url, &config).unwrap();
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].contains("\r\nAuthorization: Bearer t0ken\r\n"));
+        assert!(!requests[1].contains("Authorization"));
+    }
+
+    #[test]
+    fn test_probe_sends_headers_and_cookies() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in [
+                "HTTP/1.1 302 Found\r\nSet-Cookie: session=abc; Path=/; HttpOnly\r\nLocation: /file.bin\r\n\r\n",
+                "HTTP/1.1 200 OK\r\nContent-Length: 42\r\n\r\n",
+            ] {
+                let (mut socket, _) = listener.accept().unwrap();
+                let mut request = [0u8; 1024];
+                let n = socket.read(&mut request).unwrap();
+                requests.push(String::from_utf8_lossy(&request[..n]).into_owned());
+                socket.write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+
+        let url = Url::parse(&format!("http://127.0.0.1:{}/login", port)).unwrap();
+        let mut config = DownloadConfig::new(url.to_string(), "output.bin".to_string(), 1);
+        config.headers.user_agent = "Mozilla/5.0".into();
+        config.headers.referer = Some("https://example.com/".into());
+        config.headers.extra.push(("X-Api-Key".into(), "abc".into()));
+        config.headers.extra.push(("Cookie".into(), "theme=dark".into()));
+        let jar = Arc::new(CookieJar::new());
+        config.cookies = Some(jar.clone());
+        probe(&url, &config).unwrap();
+
+        let requests = server.join().unwrap();
+        for request in &requests {
+            assert!(request.contains("\r\nUser-Agent: Mozilla/5.0\r\n"));
+            assert!(request.contains("\r\nReferer: https://example.com/\r\n"));
+            assert!(request.contains("\r\nX-Api-Key: abc\r\n"));
+        }
+        assert!(requests[0].contains("\r\nCookie: theme=dark\r\n"));
+        assert!(requests[1].contains("\r\nCookie: theme=dark; session=abc\r\n"));
+        assert_eq!(jar.cookies().len(), 1);
+    }
+
+    #[test]
+    fn test_probe_stops_at_redirect_limit() {
+        let (port, server) = serve(vec![
+            "HTTP/1.1 302 Found\r\nLocation: /a\r\n\r\n",
+            "HTTP/1.1 302 Found\r\nLocation: /b\r\n\r\n",
+        ]);
+        let url = Url::parse(&format!("http://127.0.0.1:{}/file.bin", port)).unwrap();
+        let mut config = DownloadConfig::new(url.to_string(), "output.bin".to_string(), 1);
+        config.max_redirects = 1;
+
+        let result = probe(&url, &config);
+        assert_eq!(server.join().unwrap().len(), 2);
+        assert!(matches!(result, Err(DownloaderError::RedirectError(_))));
+    }
+
+    #[test]
+    fn test_redirect_target_refuses_downgrade() {
+        let current = Url::parse("https://example.com/file.bin").unwrap();
+        let mut config = DownloadConfig::new(current.to_string(), "output.bin".to_string(), 1);
+        let downgrade = Response::parse(b"HTTP/1.1 302 Found\r\nLocation: http://cdn.example.com/file.bin\r\n\r\n").unwrap();
+
+        let result = redirect_target(&current, &downgrade, &config, 0);
+        assert!(matches!(result, Err(DownloaderError::RedirectError(_))));
+
+        config.allow_insecure_redirects = true;
+        let next = redirect_target(&current, &downgrade, &config, 0).unwrap();
+        assert_eq!(next.unwrap().as_str(), "http://cdn.example.com/file.bin");
+
+        let ok = Response::parse(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+        assert_eq!(redirect_target(&current, &ok, &config, 0).unwrap(), None);
+        let missing = Response::parse(b"HTTP/1.1 302 Found\r\n\r\n").unwrap();
+        assert!(redirect_target(&current, &missing, &config, 0).is_err());
+    }
+}
@@ -1,11 +1,25 @@
+pub mod auth;
+pub mod body;
+pub mod checksum;
 pub mod config;
 pub mod connection;
+pub mod cookies;
 pub mod dns;
 pub mod downloader;
 pub mod error;
+pub mod filename;
 pub mod http;
+pub mod output;
+pub mod progress;
+pub mod proxy;
+pub mod request;
+pub mod response;
+pub mod retry;
+pub mod state;
 pub mod tcp;
+pub mod temp;
+pub mod util;
 
-pub use config::DownloadConfig;
+pub use config::{DownloadConfig, OverwritePolicy, WriteMode};
 pub use downloader::DownloadManager;
 pub use error::DownloaderError;
\ No newline at end of file
@@ -1,12 +1,345 @@
-use parallel_downloader::{DownloadConfig, DownloadManager, DownloaderError, dns, connection, http};
+use parallel_downloader::{DownloadConfig, DownloadManager, DownloaderError, OverwritePolicy, http};
+use parallel_downloader::auth::{self, Auth};
+use parallel_downloader::checksum::Checksum;
+use parallel_downloader::dns::{DnsOptions, Resolver};
+use parallel_downloader::filename;
+use parallel_downloader::output;
+use parallel_downloader::progress::TerminalProgress;
+use parallel_downloader::cookies::CookieJar;
+use parallel_downloader::proxy::{self, Proxy, ProxyOptions};
+use parallel_downloader::request;
+use parallel_downloader::temp;
+use clap::Parser;
 use url::Url;
-use std::io::{self, Write, BufRead};
-use std::path::Path;
+use std::io::{self, Write, BufRead, IsTerminal};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::process::ExitCode;
+use std::time::Duration;
 
-fn main() -> Result<(), DownloaderError> {
+/// Command line arguments for the downloader.
+#[derive(Parser, Debug)]
+#[command(
+    name = "parallel-downloader",
+    version,
+    about = "Download a file over multiple parallel connections",
+    after_help = "Exit status: 0 on success, 1 on an unexpected I/O error, 2 on invalid input, \
+                  3 on DNS/connection/proxy/TLS failures and timeouts, 4 on an unexpected server response, \
+                  5 on output file errors, 6 when a download cannot be resumed, \
+                  7 when the downloaded file does not match its checksum, 130 when interrupted."
+)]
+struct Cli {
+    /// URL of the file to download.
+    #[arg(required_unless_present = "interactive")]
+    url: Option<String>,
+
+    /// Output filename (defaults to the last segment of the URL path).
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<String>,
+
+    /// Number of concurrent connections (1-32).
+    #[arg(short = 'n', long = "connections", value_name = "N", default_value_t = 4, value_parser = parse_connections)]
+    connections: usize,
+
+    /// Smallest part split off a slow connection for an idle one, in bytes or with a
+    /// K, M or G suffix.
+    #[arg(long, value_name = "SIZE", default_value = "1M", value_parser = parse_size)]
+    min_segment_size: u64,
+
+    /// Keep a control file so an interrupted download can be continued, and continue
+    /// from one left by an earlier run.
+    #[arg(short = 'c', long)]
+    resume: bool,
+
+    /// What to do when the output file exists: fail, overwrite, rename (to `file (1).jpg`)
+    /// or skip-identical (download nothing if it matches the remote file).
+    #[arg(long, value_name = "POLICY", default_value = "fail", value_parser = OverwritePolicy::parse)]
+    if_exists: OverwritePolicy,
+
+    /// Directory for the temporary files of the download (defaults to the directory of
+    /// the output file).
+    #[arg(long, value_name = "DIR")]
+    temp_dir: Option<PathBuf>,
+
+    /// How many times a failed part is retried before the download fails.
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    retries: u32,
+
+    /// How many redirects are followed before giving up.
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    max_redirects: usize,
+
+    /// Follow redirects from HTTPS to plain HTTP URLs.
+    #[arg(long)]
+    allow_insecure_redirects: bool,
+
+    /// PEM file with extra CA certificates to trust, e.g. for a private CA.
+    #[arg(long, value_name = "FILE")]
+    ca_bundle: Option<PathBuf>,
+
+    /// Directory of PEM files with extra CA certificates to trust.
+    #[arg(long, value_name = "DIR")]
+    ca_dir: Option<PathBuf>,
+
+    /// Do not verify TLS certificates. Anyone on the network path can then read and
+    /// alter the download.
+    #[arg(long)]
+    insecure: bool,
+
+    /// resolv.conf file to read nameservers from instead of the system configuration.
+    #[arg(long, value_name = "FILE")]
+    resolv_conf: Option<PathBuf>,
+
+    /// Nameserver to query (`IP` or `IP:PORT`); may be given several times.
+    #[arg(long = "nameserver", value_name = "ADDR", value_parser = parse_nameserver)]
+    nameservers: Vec<SocketAddr>,
+
+    /// Query nameservers over TCP instead of UDP.
+    #[arg(long)]
+    dns_tcp: bool,
+
+    /// Spread the connections over all of the server's IP addresses.
+    #[arg(long)]
+    spread_addresses: bool,
+
+    /// Seconds to wait for a TCP connection to be established.
+    #[arg(long, value_name = "SECS", default_value = "30", value_parser = parse_seconds)]
+    connect_timeout: Duration,
+
+    /// Seconds a connection may go without receiving any data before it is abandoned.
+    #[arg(long, value_name = "SECS", default_value = "60", value_parser = parse_seconds)]
+    read_timeout: Duration,
+
+    /// Seconds the whole download may take; no limit by default.
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds)]
+    timeout: Option<Duration>,
+
+    /// Proxy for all requests (`http://[user:pass@]host:port` or `socks5h://host:port`),
+    /// instead of the one from `HTTPS_PROXY`/`HTTP_PROXY`/`ALL_PROXY`.
+    #[arg(long, value_name = "URL", value_parser = Proxy::parse)]
+    proxy: Option<Proxy>,
+
+    /// Comma-separated hosts to connect to directly, instead of those from `NO_PROXY`.
+    #[arg(long, value_name = "HOSTS")]
+    no_proxy: Option<String>,
+
+    /// Credentials for Basic authentication, as `USER[:PASSWORD]`. They are only sent
+    /// to the host of the URL, not after a redirect to another host.
+    #[arg(short, long, value_name = "USER[:PASSWORD]", group = "auth")]
+    user: Option<String>,
+
+    /// Token for Bearer authentication, sent only to the host of the URL.
+    #[arg(long, value_name = "TOKEN", group = "auth", value_parser = request::parse_value)]
+    bearer: Option<String>,
+
+    /// Read credentials for the host from ~/.netrc.
+    #[arg(long, group = "auth")]
+    netrc: bool,
+
+    /// Read credentials for the host from this netrc file.
+    #[arg(long, value_name = "FILE", group = "auth")]
+    netrc_file: Option<PathBuf>,
+
+    /// Extra request header, as `Name: value`. May be given more than once.
+    #[arg(short = 'H', long = "header", value_name = "HEADER", value_parser = request::parse_header)]
+    headers: Vec<(String, String)>,
+
+    /// User-Agent to send instead of the downloader's own.
+    #[arg(short = 'A', long, value_name = "AGENT", value_parser = request::parse_value)]
+    user_agent: Option<String>,
+
+    /// Referer to send with every request.
+    #[arg(long, value_name = "URL", value_parser = request::parse_value)]
+    referer: Option<String>,
+
+    /// Netscape-format cookie file. Its cookies are sent with the requests, and cookies
+    /// set by the server are written back to it.
+    #[arg(long, value_name = "FILE")]
+    cookies: Option<PathBuf>,
+
+    /// Expected digest of the file, as `sha256:<hex>` (also sha512, sha1 or md5).
+    #[arg(long, value_name = "ALGO:HEX", value_parser = Checksum::parse)]
+    checksum: Option<Checksum>,
+
+    /// Checksum file (e.g. `SHA256SUMS`) listing the expected digest of the file.
+    #[arg(long, value_name = "FILE", conflicts_with = "checksum")]
+    checksum_file: Option<PathBuf>,
+
+    /// Print nothing but errors: no progress display and no status messages.
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Start the interactive menu instead of downloading the URL given on the command line.
+    #[arg(short, long, conflicts_with_all = ["url", "output", "resume"])]
+    interactive: bool,
+}
+
+/// Exit status after Ctrl-C, as for a shell command killed by `SIGINT`.
+const INTERRUPTED: i32 = 130;
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    // Leave no temporary files behind when interrupted. Downloads that can be resumed
+    // do not register theirs, so they are kept.
+    let handler = ctrlc::set_handler(|| {
+        temp::remove_registered();
+        eprintln!("\nInterrupted.");
+        std::process::exit(INTERRUPTED);
+    });
+    if let Err(e) = handler {
+        eprintln!("Warning: could not install the Ctrl-C handler: {}", e);
+    }
+
+    let result = if cli.interactive {
+        run_interactive()
+    } else {
+        run_from_args(&cli)
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("\nError: {}", e);
+            exit_code(&e)
+        }
+    }
+}
+
+/// Maps a `DownloaderError` to the process exit status documented in `--help`.
+fn exit_code(error: &DownloaderError) -> ExitCode {
+    let code = match error {
+        DownloaderError::IoError(_) | DownloaderError::ThreadError(_) => 1,
+        DownloaderError::UserInputError(_) | DownloaderError::UrlParseError(_) => 2,
+        DownloaderError::DnsError(_)
+        | DownloaderError::ConnectionError(_)
+        | DownloaderError::ProxyError(_)
+        | DownloaderError::TlsError(_)
+        | DownloaderError::TlsHandshakeError(_)
+        | DownloaderError::Timeout(_) => 3,
+        DownloaderError::ResponseError(_)
+        | DownloaderError::HttpStatus(..)
+        | DownloaderError::RedirectError(_) => 4,
+        DownloaderError::FileError(_) => 5,
+        DownloaderError::ResumeError(_) => 6,
+        DownloaderError::ChecksumError(_) => 7,
+    };
+    ExitCode::from(code)
+}
+
+/// Validates the `--connections` argument.
+fn parse_connections(value: &str) -> Result<usize, String> {
+    let num_connections: usize = value
+        .parse()
+        .map_err(|_| format!("`{}` is not a number", value))?;
+    if !(1..=32).contains(&num_connections) {
+        return Err("number of connections must be between 1 and 32".into());
+    }
+    Ok(num_connections)
+}
+
+/// Parses a size in bytes, optionally with a binary `K`, `M` or `G` suffix.
+fn parse_size(value: &str) -> Result<u64, String> {
+    let (digits, unit) = match value.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => value.split_at(i),
+        None => (value, ""),
+    };
+    let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        _ => return Err(format!("`{}` is not a size; use e.g. 512K or 4M", value)),
+    };
+    match digits.parse::<u64>().ok().and_then(|n| n.checked_mul(multiplier)) {
+        Some(0) => Err("size must be at least 1 byte".into()),
+        Some(size) => Ok(size),
+        None => Err(format!("`{}` is not a size; use e.g. 512K or 4M", value)),
+    }
+}
+
+/// Validates a `--nameserver` argument, defaulting to port 53.
+fn parse_nameserver(value: &str) -> Result<SocketAddr, String> {
+    if let Ok(ip) = value.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, 53));
+    }
+    value
+        .parse()
+        .map_err(|_| format!("`{}` is not an IP address or IP:PORT", value))
+}
+
+/// Validates a timeout given in whole seconds.
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    match value.parse::<u64>() {
+        Ok(0) => Err("timeout must be at least 1 second".into()),
+        Ok(seconds) => Ok(Duration::from_secs(seconds)),
+        Err(_) => Err(format!("`{}` is not a number of seconds", value)),
+    }
+}
+
+fn run_from_args(cli: &Cli) -> Result<(), DownloaderError> {
+    let url_input = cli
+        .url
+        .clone()
+        .ok_or_else(|| DownloaderError::UserInputError("A URL is required".into()))?;
+    // Fail on a malformed URL before loading any files.
+    Url::parse(&url_input)?;
+
+    // The output name is chosen once the server has been probed.
+    let mut config = DownloadConfig::new(url_input, String::new(), cli.connections);
+    config.min_segment_size = cli.min_segment_size;
+    config.resume = cli.resume;
+    config.overwrite = cli.if_exists;
+    config.temp_dir = cli.temp_dir.clone();
+    config.retry.max_retries = cli.retries;
+    config.max_redirects = cli.max_redirects;
+    config.allow_insecure_redirects = cli.allow_insecure_redirects;
+    config.tls.insecure = cli.insecure;
+    config.tls.ca_bundle = cli.ca_bundle.clone();
+    config.tls.ca_dir = cli.ca_dir.clone();
+    config.resolver = Arc::new(Resolver::new(DnsOptions {
+        resolv_conf: cli.resolv_conf.clone(),
+        nameservers: cli.nameservers.clone(),
+        use_tcp: cli.dns_tcp,
+    }));
+    config.spread_addresses = cli.spread_addresses;
+    config.timeouts.connect = cli.connect_timeout;
+    config.timeouts.read = cli.read_timeout;
+    config.timeouts.download = cli.timeout;
+    config.checksum = cli.checksum.clone();
+    config.auth = if let Some(user) = &cli.user {
+        Auth::basic(user)
+    } else if let Some(token) = &cli.bearer {
+        Auth::Bearer(token.clone())
+    } else if cli.netrc || cli.netrc_file.is_some() {
+        Auth::Netrc(cli.netrc_file.clone())
+    } else {
+        Auth::None
+    };
+    if let Some(user_agent) = &cli.user_agent {
+        config.headers.user_agent = user_agent.clone();
+    }
+    config.headers.referer = cli.referer.clone();
+    config.headers.extra = cli.headers.clone();
+    if let Some(path) = &cli.cookies {
+        config.cookies = Some(Arc::new(CookieJar::load(path)?));
+    }
+    config.proxy = ProxyOptions::from_env()?;
+    if let Some(proxy) = &cli.proxy {
+        config.proxy.http = Some(proxy.clone());
+        config.proxy.https = Some(proxy.clone());
+    }
+    if let Some(hosts) = &cli.no_proxy {
+        config.proxy.no_proxy = proxy::parse_no_proxy(hosts);
+    }
+    run_download(config, cli.output.as_deref(), cli.checksum_file.as_deref(), cli.quiet)
+}
+
+fn run_interactive() -> Result<(), DownloaderError> {
     let stdin = io::stdin();
     let mut stdin_lock = stdin.lock();
-    
+
     println!("\n=== Parallel File Downloader ===\n");
 
     loop {
@@ -17,9 +350,13 @@ fn main() -> Result<(), DownloaderError> {
         io::stdout().flush()?;
 
         let mut choice = String::new();
-        if stdin_lock.read_line(&mut choice).is_err() {
-            println!("Error reading input. Please try again.");
-            continue;
+        match stdin_lock.read_line(&mut choice) {
+            Ok(0) => break, // End of input
+            Ok(_) => {}
+            Err(_) => {
+                println!("Error reading input. Please try again.");
+                continue;
+            }
         }
 
         match choice.trim() {
@@ -49,7 +386,7 @@ fn handle_download(stdin: &mut impl BufRead) -> Result<(), DownloaderError> {
     let url_input = url_input.trim().to_string();
 
     // Validate URL
-    let url = Url::parse(&url_input)?;
+    Url::parse(&url_input)?;
 
     // Get number of connections
     print!("Enter number of connections (1-32, default 4): ");
@@ -58,68 +395,353 @@ fn handle_download(stdin: &mut impl BufRead) -> Result<(), DownloaderError> {
     stdin.read_line(&mut connections_input)?;
     let num_connections = connections_input.trim().parse().unwrap_or(4);
 
-    if num_connections < 1 || num_connections > 32 {
+    if !(1..=32).contains(&num_connections) {
         return Err(DownloaderError::UserInputError("Number of connections must be between 1 and 32".into()));
     }
 
-    // Determine the default filename from the URL path
-    let default_filename = url.path_segments()
-        .and_then(|segments| segments.last())
-        .and_then(|last_segment| Some(last_segment.to_string()))
-        .unwrap_or("downloaded_file".to_string());
-
-    print!("Enter output filename (default: {}): ", default_filename);
+    print!("Enter output filename (default: the name suggested by the server): ");
     io::stdout().flush()?;
     let mut filename_input = String::new();
     stdin.read_line(&mut filename_input)?;
-    let output_filename = if filename_input.trim().is_empty() {
-        default_filename.clone()
+    let requested = Some(filename_input.trim()).filter(|name| !name.is_empty());
+
+    let mut config = DownloadConfig::new(url_input, String::new(), num_connections);
+    config.proxy = ProxyOptions::from_env()?;
+    run_download(config, requested, None, false)
+}
+
+/// Uses the requested filename, borrowing the default's extension if it has none.
+fn output_filename(requested: &str, default_filename: &str) -> String {
+    if Path::new(requested).extension().is_none() {
+        let extension = Path::new(default_filename)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("dat");
+        format!("{}.{}", requested, extension)
     } else {
-        let input_trimmed = filename_input.trim();
-        if Path::new(input_trimmed).extension().is_none() {
-            format!("{}.{}", input_trimmed, Path::new(&default_filename).extension().unwrap_or_default().to_str().unwrap_or("dat"))
-        } else {
-            input_trimmed.to_string()
+        requested.to_string()
+    }
+}
+
+/// Probes the URL and downloads it, printing what happens unless `quiet` is set.
+///
+/// The output file is named after the probe: `requested` if given (borrowing the
+/// extension of the server's suggested name when it has none), or else the name the
+/// server or URL suggests. When `requested` has an extension, the overwrite policy is
+/// applied to it before the probe, so that nothing is requested for a download that may
+/// not be written. The expected checksum is looked up in `checksum_file` under
+/// the suggested name. A progress display is drawn on standard error when it is a
+/// terminal.
+fn run_download(
+    mut config: DownloadConfig,
+    requested: Option<&str>,
+    checksum_file: Option<&Path>,
+    quiet: bool,
+) -> Result<(), DownloaderError> {
+    let say = |message: String| {
+        if !quiet {
+            println!("{}", message);
         }
     };
 
-    println!("\nInitializing download...");
+    // Refuse unsafe names, and names the overwrite policy does not allow, before
+    // contacting the server. A name without an extension takes the server's, so it is
+    // only known after the probe.
+    let mut requested = requested.map(str::to_string);
+    if let Some(name) = &requested {
+        output::validate_path(name)?;
+        if Path::new(name).extension().is_some() {
+            config.output_file = name.clone();
+            if let Some(resolved) = output::resolve_local(&config)? {
+                requested = Some(resolved);
+            }
+        }
+    }
+
+    say("\nInitializing download...".into());
+    if config.tls.insecure {
+        eprintln!(
+            "\nWARNING: TLS certificate verification is disabled (--insecure). \
+             The server's identity is not checked and the download can be intercepted or altered."
+        );
+    }
+
+    let url = Url::parse(&config.url)?;
+    if let Some(proxy) = config.proxy.for_url(&url) {
+        say(format!("Using proxy: {}:{}", proxy.host, proxy.port));
+    }
+
+    say("Checking file details...".into());
+    let info = http::probe(&url, &config)?;
+    if info.url != url {
+        say(format!("Redirected to: {}", auth::redact(info.url.as_str())));
+    }
+
+    match info.total_size {
+        Some(size) => say(format!("\nFile size: {} bytes", size)),
+        None => say("\nFile size: unknown".into()),
+    }
+    let suggested = filename::suggested_filename(&url, &info);
+    if let Some(path) = checksum_file {
+        config.checksum = Some(Checksum::from_sums_file(path, &suggested)?);
+    }
+    config.output_file = match &requested {
+        Some(requested) => output_filename(requested, &suggested),
+        None => suggested,
+    };
+
+    if !quiet && io::stderr().is_terminal() {
+        config.progress = Some(Arc::new(TerminalProgress::new()));
+    }
+    let num_connections = config.num_connections;
+    let manager = DownloadManager::from_resource(config, &info)?;
+    let output_file = manager.output_file().to_string();
+    if manager.is_skipped() {
+        say(format!("'{}' is already up to date; nothing to download.\n", output_file));
+        return Ok(());
+    }
+    say(format!("Output file: {}", output_file));
+    match manager.fallback_reason() {
+        Some(reason) => say(format!("Parallel download disabled: {}; using a single connection.\n", reason)),
+        None if manager.num_parts() == 0 => say("The file is empty; nothing to download.\n".into()),
+        None => say(format!("Number of connections: {}\n", num_connections.min(manager.num_parts()))),
+    }
+    say("Starting download...".into());
+    manager.download()?;
+
+    say("\nDownload completed successfully!".into());
+    say(format!("File saved as: {}\n", output_file));
 
-    let config = DownloadConfig::new(
-        url_input,
-        output_filename.clone(),
-        num_connections,
-    );
+    Ok(())
+}
 
-    let hostname = url.host_str()
-        .ok_or_else(|| DownloaderError::UrlParseError(url::ParseError::EmptyHost))?;
-    let path = url.path();
+#[cfg(test)]
+mod tests {
+    use super::{output_filename, parse_nameserver, parse_size, run_download, Cli};
+    use parallel_downloader::filename;
+    use parallel_downloader::{DownloadConfig, DownloaderError, OverwritePolicy};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+    use clap::Parser;
+    use url::Url;
 
-    println!("Resolving hostname...");
-    let ip = dns::get_request_ip(hostname)?;
+    #[test]
+    fn test_cli_parses_download_arguments() {
+        let cli = Cli::try_parse_from([
+            "parallel-downloader",
+            "https://example.com/image.jpg",
+            "-o",
+            "out.jpg",
+            "-n",
+            "8",
+        ])
+        .unwrap();
+        assert_eq!(cli.url.as_deref(), Some("https://example.com/image.jpg"));
+        assert_eq!(cli.output.as_deref(), Some("out.jpg"));
+        assert_eq!(cli.connections, 8);
+        assert!(!cli.interactive);
+        assert!(!cli.quiet);
 
-    println!("Establishing connection...");
-    let mut stream = connection::establish_tls_connection(hostname, ip)?;
+        let cli = Cli::try_parse_from(["parallel-downloader", "https://example.com/image.jpg", "-q"]).unwrap();
+        assert!(cli.quiet);
+    }
 
-    println!("Checking file details...");
-    let (supports_range, total_size) = http::send_head_request(&mut stream, hostname, path)?;
+    #[test]
+    fn test_cli_if_exists() {
+        let cli = Cli::try_parse_from(["parallel-downloader", "https://example.com/a"]).unwrap();
+        assert_eq!(cli.if_exists, OverwritePolicy::Fail);
+        let cli = Cli::try_parse_from(["parallel-downloader", "https://example.com/a", "--if-exists", "rename"]).unwrap();
+        assert_eq!(cli.if_exists, OverwritePolicy::Rename);
+        assert!(Cli::try_parse_from(["parallel-downloader", "https://example.com/a", "--if-exists", "clobber"]).is_err());
+    }
 
-    if !supports_range {
-        return Err(DownloaderError::ResponseError(
-            "Server does not support range requests".into(),
-        ));
+    #[test]
+    fn test_cli_temp_dir() {
+        let cli = Cli::try_parse_from(["parallel-downloader", "https://example.com/a"]).unwrap();
+        assert_eq!(cli.temp_dir, None);
+        let cli = Cli::try_parse_from(["parallel-downloader", "https://example.com/a", "--temp-dir", "/tmp/parts"]).unwrap();
+        assert_eq!(cli.temp_dir, Some(PathBuf::from("/tmp/parts")));
     }
 
-    println!("\nFile size: {} bytes", total_size);
-    println!("Output file: {}", output_filename);
-    println!("Number of connections: {}\n", num_connections);
-    println!("Starting download...");
+    #[test]
+    fn test_cli_requires_url_unless_interactive() {
+        assert!(Cli::try_parse_from(["parallel-downloader"]).is_err());
+        let cli = Cli::try_parse_from(["parallel-downloader", "--interactive"]).unwrap();
+        assert!(cli.interactive);
+    }
 
-    let manager = DownloadManager::new(config, total_size);
-    manager.download()?;
+    #[test]
+    fn test_cli_rejects_out_of_range_connections() {
+        assert!(Cli::try_parse_from(["parallel-downloader", "https://example.com/a", "-n", "0"]).is_err());
+        assert!(Cli::try_parse_from(["parallel-downloader", "https://example.com/a", "-n", "33"]).is_err());
+    }
 
-    println!("\nDownload completed successfully!");
-    println!("File saved as: {}\n", output_filename);
+    #[test]
+    fn test_cli_min_segment_size() {
+        let cli = Cli::try_parse_from(["parallel-downloader", "https://example.com/a"]).unwrap();
+        assert_eq!(cli.min_segment_size, 1024 * 1024);
 
-    Ok(())
-}
\ No newline at end of file
+        let cli =
+            Cli::try_parse_from(["parallel-downloader", "https://example.com/a", "--min-segment-size", "512K"]).unwrap();
+        assert_eq!(cli.min_segment_size, 512 * 1024);
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("2g"), Ok(2 * 1024 * 1024 * 1024));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("1.5M").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn test_cli_tls_options() {
+        let cli = Cli::try_parse_from(["parallel-downloader", "https://example.com/a"]).unwrap();
+        assert!(!cli.insecure);
+        assert_eq!(cli.ca_bundle, None);
+
+        let cli = Cli::try_parse_from([
+            "parallel-downloader",
+            "https://mirror.internal/a",
+            "--ca-bundle",
+            "/etc/ssl/internal-ca.pem",
+            "--insecure",
+        ])
+        .unwrap();
+        assert!(cli.insecure);
+        assert_eq!(cli.ca_bundle.as_deref(), Some(Path::new("/etc/ssl/internal-ca.pem")));
+    }
+
+    #[test]
+    fn test_cli_auth_options() {
+        let cli = Cli::try_parse_from(["parallel-downloader", "https://example.com/a", "-u", "alice:s3cret"]).unwrap();
+        assert_eq!(cli.user.as_deref(), Some("alice:s3cret"));
+        let cli = Cli::try_parse_from(["parallel-downloader", "https://example.com/a", "--netrc"]).unwrap();
+        assert!(cli.netrc);
+        assert!(Cli::try_parse_from([
+            "parallel-downloader",
+            "https://example.com/a",
+            "--user",
+            "alice",
+            "--bearer",
+            "t0ken",
+        ])
+        .is_err());
+        assert!(Cli::try_parse_from(["parallel-downloader", "https://example.com/a", "--bearer", "a\r\nX: y"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_header_options() {
+        let cli = Cli::try_parse_from([
+            "parallel-downloader",
+            "https://example.com/a",
+            "-H",
+            "X-Api-Key: abc",
+            "--header",
+            "Accept: */*",
+            "-A",
+            "Mozilla/5.0",
+            "--cookies",
+            "cookies.txt",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.headers,
+            vec![("X-Api-Key".to_string(), "abc".to_string()), ("Accept".to_string(), "*/*".to_string())]
+        );
+        assert_eq!(cli.user_agent.as_deref(), Some("Mozilla/5.0"));
+        assert_eq!(cli.cookies.as_deref(), Some(Path::new("cookies.txt")));
+        assert!(Cli::try_parse_from(["parallel-downloader", "https://example.com/a", "-H", "Range: bytes=0-"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_timeouts() {
+        let cli = Cli::try_parse_from(["parallel-downloader", "https://example.com/a"]).unwrap();
+        assert_eq!(cli.connect_timeout, Duration::from_secs(30));
+        assert_eq!(cli.read_timeout, Duration::from_secs(60));
+        assert_eq!(cli.timeout, None);
+
+        let cli = Cli::try_parse_from([
+            "parallel-downloader",
+            "https://example.com/a",
+            "--connect-timeout",
+            "5",
+            "--timeout",
+            "600",
+        ])
+        .unwrap();
+        assert_eq!(cli.connect_timeout, Duration::from_secs(5));
+        assert_eq!(cli.timeout, Some(Duration::from_secs(600)));
+        assert!(Cli::try_parse_from(["parallel-downloader", "https://example.com/a", "--read-timeout", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_proxy_options() {
+        let cli = Cli::try_parse_from([
+            "parallel-downloader",
+            "https://example.com/a",
+            "--proxy",
+            "socks5h://127.0.0.1:1080",
+            "--no-proxy",
+            "localhost,.internal",
+        ])
+        .unwrap();
+        assert_eq!(cli.proxy.map(|proxy| proxy.port), Some(1080));
+        assert_eq!(cli.no_proxy.as_deref(), Some("localhost,.internal"));
+        assert!(Cli::try_parse_from(["parallel-downloader", "https://example.com/a", "--proxy", "ftp://p"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_checksum_options() {
+        let cli = Cli::try_parse_from([
+            "parallel-downloader",
+            "https://example.com/a.iso",
+            "--checksum",
+            "md5:5d41402abc4b2a76b9719d911017c592",
+        ])
+        .unwrap();
+        assert_eq!(cli.checksum.map(|checksum| checksum.to_string()).as_deref(), Some("md5:5d41402abc4b2a76b9719d911017c592"));
+        assert!(Cli::try_parse_from(["parallel-downloader", "https://example.com/a.iso", "--checksum", "sha256:00"]).is_err());
+        assert!(Cli::try_parse_from([
+            "parallel-downloader",
+            "https://example.com/a.iso",
+            "--checksum",
+            "md5:5d41402abc4b2a76b9719d911017c592",
+            "--checksum-file",
+            "SHA256SUMS",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_nameserver() {
+        assert_eq!(parse_nameserver("1.1.1.1").unwrap(), "1.1.1.1:53".parse().unwrap());
+        assert_eq!(parse_nameserver("[::1]:5353").unwrap(), "[::1]:5353".parse().unwrap());
+        assert!(parse_nameserver("dns.example.com").is_err());
+    }
+
+    #[test]
+    fn test_output_filename_defaults() {
+        let url = Url::parse("https://example.com/files/photo.png").unwrap();
+        assert_eq!(filename::url_filename(&url).as_deref(), Some("photo.png"));
+        assert_eq!(output_filename("holiday", "photo.png"), "holiday.png");
+        assert_eq!(output_filename("holiday.jpg", "photo.png"), "holiday.jpg");
+
+        let url = Url::parse("https://example.com/").unwrap();
+        assert_eq!(filename::url_filename(&url), None);
+        assert_eq!(output_filename("holiday", "downloaded_file"), "holiday.dat");
+    }
+
+    #[test]
+    fn test_existing_output_fails_before_connecting() {
+        let dir = std::env::temp_dir().join(format!("pd-cli-exists-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("file.bin").to_string_lossy().into_owned();
+        fs::write(&output, b"old").unwrap();
+
+        // Nothing listens on port 1, so probing would fail with a network error.
+        let config = DownloadConfig::new("http://127.0.0.1:1/file.bin".into(), String::new(), 4);
+        let result = run_download(config, Some(&output), None, true);
+        assert!(matches!(result, Err(DownloaderError::FileError(_))));
+        assert_eq!(fs::read(&output).unwrap(), b"old");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,459 @@
+//! # Output File Handling
+//!
+//! This module manages the file the download is written into. The output is created
+//! under a temporary `.partial` name (see `temp::partial_path`), preallocated to the
+//! full size of the download, and filled in place by the workers using positional
+//! writes. Once every part is complete the file is atomically renamed to its final name.
+//!
+//! ## Features
+//! - Preallocates the temporary output file, or reopens it to resume.
+//! - Lets each worker write at its own offset without coordination.
+//! - Atomically renames the completed file into place.
+//! - Moves files that fail verification aside instead of into place.
+//! - Rejects output names with `..` components or control characters.
+//! - Applies the overwrite policy to an existing output file: fail, overwrite, pick a
+//!   free `file (1).jpg` name, or skip an identical file.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use crate::checksum::{Checksum, Verifier};
+use crate::config::{DownloadConfig, OverwritePolicy};
+use crate::cookies::parse_http_date;
+use crate::error::DownloaderError;
+use crate::http::ResourceInfo;
+use crate::temp;
+
+/// Creates (or truncates) `path` and preallocates it to `size` bytes.
+///
+/// # Parameters
+/// - `path`: The file to create.
+/// - `size`: The final size of the file.
+///
+/// # Returns
+/// The opened file, shared so that every worker can write into it.
+pub fn create_preallocated(path: &Path, size: u64) -> Result<Arc<File>, DownloaderError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    file.set_len(size)?;
+    Ok(Arc::new(file))
+}
+
+/// Reopens a preallocated file left behind by an interrupted download.
+///
+/// # Parameters
+/// - `path`: The file to reopen.
+/// - `size`: The final size of the file.
+///
+/// # Errors
+/// Returns a `DownloaderError` if the file is missing or does not have the expected size.
+pub fn open_preallocated(path: &Path, size: u64) -> Result<Arc<File>, DownloaderError> {
+    let file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = file.metadata()?.len();
+    if len != size {
+        return Err(DownloaderError::FileError(format!(
+            "'{}' is {} bytes, expected {}",
+            path.display(),
+            len,
+            size
+        )));
+    }
+    Ok(Arc::new(file))
+}
+
+/// Opens a part file so that writing continues after its first `offset` bytes.
+///
+/// Anything beyond `offset` is discarded; a missing file is created.
+pub fn open_part_file(path: &Path, offset: u64) -> Result<File, DownloaderError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().write(true).create(true).truncate(false).open(path)?;
+    file.set_len(offset)?;
+    file.seek(SeekFrom::Start(offset))?;
+    Ok(file)
+}
+
+/// Atomically moves the completed temporary file to its final name. A temporary file on
+/// another filesystem, e.g. in a separate temp directory, is copied instead.
+///
+/// # Parameters
+/// - `partial`: The temporary file.
+/// - `output_file`: The final name of the output file.
+pub fn finalize(partial: &Path, output_file: &str) -> Result<(), DownloaderError> {
+    move_file(partial, Path::new(output_file)).map_err(|e| {
+        DownloaderError::FileError(format!(
+            "Could not rename '{}' to '{}': {}",
+            partial.display(),
+            output_file,
+            e
+        ))
+    })
+}
+
+/// Returns the name a file that failed verification is moved to.
+///
+/// # Parameters
+/// - `output_file`: The final name of the output file.
+///
+/// # Returns
+/// The output path with `.corrupt` appended.
+pub fn quarantine_path(output_file: &str) -> PathBuf {
+    PathBuf::from(format!("{}.corrupt", output_file))
+}
+
+/// Moves a completed temporary file that failed verification to its quarantine name,
+/// so that it neither takes the place of the output nor is mistaken for a resumable
+/// download.
+///
+/// # Parameters
+/// - `partial`: The temporary file.
+/// - `output_file`: The final name of the output file.
+pub fn quarantine(partial: &Path, output_file: &str) -> Result<(), DownloaderError> {
+    let corrupt = quarantine_path(output_file);
+    move_file(partial, &corrupt).map_err(|e| {
+        DownloaderError::FileError(format!(
+            "Could not rename '{}' to '{}': {}",
+            partial.display(),
+            corrupt.display(),
+            e
+        ))
+    })
+}
+
+/// Renames `from` to `to`, or copies and removes it when they are on different
+/// filesystems.
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(from, to)?;
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+/// Checks that `output_file` is a safe name to write the download to.
+///
+/// # Errors
+/// Returns `DownloaderError::UserInputError` if the name is empty, names a directory,
+/// contains a `..` component or contains control characters.
+pub fn validate_path(output_file: &str) -> Result<(), DownloaderError> {
+    let invalid = |reason: &str| {
+        DownloaderError::UserInputError(format!("Invalid output file '{}': {}", output_file.escape_debug(), reason))
+    };
+    if output_file.chars().any(char::is_control) {
+        return Err(invalid("it contains control characters"));
+    }
+    let path = Path::new(output_file);
+    if path.components().any(|component| component == Component::ParentDir) {
+        return Err(invalid("it must not contain '..'"));
+    }
+    if output_file.ends_with(['/', '\\']) || path.file_name().is_none() {
+        return Err(invalid("it does not name a file"));
+    }
+    Ok(())
+}
+
+/// Decides where the download goes according to `config.overwrite`, before any part is
+/// requested.
+///
+/// # Parameters
+/// - `config`: The download configuration, with the output file and overwrite policy.
+/// - `info`: The metadata returned by `http::probe`, compared with an existing file by
+///   `OverwritePolicy::SkipIfIdentical`.
+///
+/// # Returns
+/// The name to write the download to, or `None` if the existing file is identical and
+/// nothing needs to be downloaded.
+///
+/// # Errors
+/// See [`resolve_local`].
+pub fn resolve_output(config: &DownloadConfig, info: &ResourceInfo) -> Result<Option<String>, DownloaderError> {
+    if let Some(output_file) = resolve_local(config)? {
+        return Ok(Some(output_file));
+    }
+    if is_identical(Path::new(&config.output_file), info, config.checksum.as_ref())? {
+        Ok(None)
+    } else {
+        Ok(Some(config.output_file.clone()))
+    }
+}
+
+/// Applies `config.overwrite` as far as it can be without contacting the server, so that
+/// a download that may not be written fails before any request is made.
+///
+/// # Parameters
+/// - `config`: The download configuration, with the output file and overwrite policy.
+///
+/// # Returns
+/// The name to write the download to, or `None` if the file exists and the policy is
+/// `OverwritePolicy::SkipIfIdentical`, which needs the remote metadata to decide.
+///
+/// # Errors
+/// Returns `DownloaderError::UserInputError` if the name is invalid (see
+/// [`validate_path`]), and `DownloaderError::FileError` if the file exists and the policy
+/// is `OverwritePolicy::Fail`.
+pub fn resolve_local(config: &DownloadConfig) -> Result<Option<String>, DownloaderError> {
+    validate_path(&config.output_file)?;
+    if !Path::new(&config.output_file).exists() {
+        return Ok(Some(config.output_file.clone()));
+    }
+    match config.overwrite {
+        OverwritePolicy::Fail => Err(exists_error(&config.output_file)),
+        OverwritePolicy::Overwrite => Ok(Some(config.output_file.clone())),
+        OverwritePolicy::Rename => Ok(Some(available_path(&config.output_file, config.temp_dir.as_deref()))),
+        OverwritePolicy::SkipIfIdentical => Ok(None),
+    }
+}
+
+/// The error for an output file that exists and may not be replaced.
+pub fn exists_error(output_file: &str) -> DownloaderError {
+    DownloaderError::FileError(format!(
+        "'{}' already exists; choose another name or another overwrite policy",
+        output_file
+    ))
+}
+
+/// Returns `output_file` with ` (N)` inserted before its extension, e.g. `file (1).jpg`.
+pub fn numbered_path(output_file: &str, n: usize) -> String {
+    let path = Path::new(output_file);
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(extension) => format!("{} ({}).{}", stem, n, extension.to_string_lossy()),
+        None => format!("{} ({})", stem, n),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// Returns the first name `numbered_path(output_file, N)` for which neither the file
+/// nor a `.partial` file in `temp_dir` exists, so that it clashes with no finished or
+/// running download.
+pub fn available_path(output_file: &str, temp_dir: Option<&Path>) -> String {
+    (1..)
+        .map(|n| numbered_path(output_file, n))
+        .find(|candidate| !Path::new(candidate).exists() && !temp::partial_exists(candidate, temp_dir))
+        .unwrap_or_else(|| output_file.to_string())
+}
+
+/// Decides whether the file at `path` is the remote file described by `info`.
+///
+/// The sizes must match. Then, if a checksum is given, the file must have that digest;
+/// otherwise the file must be no older than the server's `Last-Modified`. Without
+/// either, the file is not taken to be identical.
+///
+/// # Errors
+/// Returns a `DownloaderError` if the file cannot be read.
+pub fn is_identical(path: &Path, info: &ResourceInfo, checksum: Option<&Checksum>) -> Result<bool, DownloaderError> {
+    let metadata = fs::metadata(path)?;
+    if info.total_size != Some(metadata.len()) {
+        return Ok(false);
+    }
+    if let Some(checksum) = checksum {
+        let verifier = Verifier::new(checksum.clone());
+        verifier.read_rest(&File::open(path)?, metadata.len())?;
+        return Ok(verifier.verify().is_ok());
+    }
+    let Some(last_modified) = info.last_modified.as_deref().and_then(parse_http_date) else {
+        return Ok(false);
+    };
+    let modified = metadata
+        .modified()?
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    Ok(modified >= last_modified)
+}
+
+/// A writer that writes sequentially into a shared file starting at a fixed offset.
+///
+/// Writes use positional I/O, so several `PositionalWriter`s can fill disjoint regions of
+/// the same file concurrently.
+pub struct PositionalWriter {
+    file: Arc<File>,
+    offset: u64,
+}
+
+impl PositionalWriter {
+    /// Creates a writer that starts writing at `offset`.
+    pub fn new(file: Arc<File>, offset: u64) -> Self {
+        Self { file, offset }
+    }
+
+    /// The offset the next write will go to.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+impl Write for PositionalWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = write_at(&self.file, buf, self.offset)?;
+        self.offset += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn write_at(file: &File, buf: &[u8], offset: u64) -> io::Result<usize> {
+    use std::os::unix::fs::FileExt;
+    file.write_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn write_at(file: &File, buf: &[u8], offset: u64) -> io::Result<usize> {
+    use std::os::windows::fs::FileExt;
+    file.seek_write(buf, offset)
+}
+
+/// Reads from `file` at `offset` without moving a shared cursor.
+#[cfg(unix)]
+pub fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    use std::os::unix::fs::FileExt;
+    file.read_at(buf, offset)
+}
+
+/// Reads from `file` at `offset` without moving a shared cursor.
+#[cfg(windows)]
+pub fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    use std::os::windows::fs::FileExt;
+    file.seek_read(buf, offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        available_path, create_preallocated, finalize, is_identical, numbered_path, open_part_file, open_preallocated,
+        resolve_local, validate_path, PositionalWriter,
+    };
+    use crate::config::{DownloadConfig, OverwritePolicy};
+    use crate::error::DownloaderError;
+    use crate::http::ResourceInfo;
+    use crate::temp;
+    use std::fs;
+    use std::io::Write;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_positional_writes_fill_preallocated_file() {
+        let dir = std::env::temp_dir().join(format!("pd-output-test-{}", std::process::id()));
+        let output = dir.join("file.bin");
+        let output = output.to_str().unwrap();
+        let partial = temp::partial_path(output, None, "0123456789ab");
+
+        let file = create_preallocated(&partial, 10).unwrap();
+        assert_eq!(fs::metadata(&partial).unwrap().len(), 10);
+
+        let mut second = PositionalWriter::new(Arc::clone(&file), 5);
+        second.write_all(b"world").unwrap();
+        assert_eq!(second.offset(), 10);
+        let mut first = PositionalWriter::new(file, 0);
+        first.write_all(b"hello").unwrap();
+
+        assert!(open_preallocated(&partial, 10).is_ok());
+        assert!(open_preallocated(&partial, 11).is_err());
+
+        finalize(&partial, output).unwrap();
+        assert!(!partial.exists());
+        assert_eq!(fs::read(output).unwrap(), b"helloworld");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_open_part_file_continues_at_offset() {
+        let dir = std::env::temp_dir().join(format!("pd-part-file-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("part0.bin");
+        fs::write(&path, b"abcdefgh").unwrap();
+
+        let mut file = open_part_file(&path, 3).unwrap();
+        file.write_all(b"XY").unwrap();
+        drop(file);
+        assert_eq!(fs::read(&path).unwrap(), b"abcXY");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_validate_path() {
+        assert!(validate_path("file.bin").is_ok());
+        assert!(validate_path("downloads/file.bin").is_ok());
+        assert!(validate_path("/tmp/file.bin").is_ok());
+        assert!(validate_path("../file.bin").is_err());
+        assert!(validate_path("downloads/../../etc/passwd").is_err());
+        assert!(validate_path("file\nname.bin").is_err());
+        assert!(validate_path("file\u{1b}[31m.bin").is_err());
+        assert!(validate_path("downloads/").is_err());
+        assert!(validate_path("").is_err());
+    }
+
+    #[test]
+    fn test_numbered_and_available_paths() {
+        assert_eq!(numbered_path("image.jpg", 1), "image (1).jpg");
+        assert_eq!(numbered_path("downloads/README", 2), "downloads/README (2)");
+
+        let dir = std::env::temp_dir().join(format!("pd-available-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("image.jpg").to_string_lossy().into_owned();
+        fs::write(&output, b"a").unwrap();
+        fs::write(dir.join("image (1).jpg"), b"b").unwrap();
+        fs::write(temp::partial_path(&dir.join("image (2).jpg").to_string_lossy(), None, "0123456789ab"), b"c").unwrap();
+        assert_eq!(available_path(&output, None), dir.join("image (3).jpg").to_string_lossy());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_local() {
+        let dir = std::env::temp_dir().join(format!("pd-resolve-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("file.bin").to_string_lossy().into_owned();
+        let mut config = DownloadConfig::new("https://example.com/file.bin".into(), output.clone(), 4);
+        assert_eq!(resolve_local(&config).unwrap(), Some(output.clone()));
+
+        fs::write(&output, b"old").unwrap();
+        assert!(matches!(resolve_local(&config), Err(DownloaderError::FileError(_))));
+        config.overwrite = OverwritePolicy::Overwrite;
+        assert_eq!(resolve_local(&config).unwrap(), Some(output.clone()));
+        config.overwrite = OverwritePolicy::Rename;
+        assert_eq!(resolve_local(&config).unwrap(), Some(dir.join("file (1).bin").to_string_lossy().into_owned()));
+        config.overwrite = OverwritePolicy::SkipIfIdentical;
+        assert_eq!(resolve_local(&config).unwrap(), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_is_identical_by_size_and_date() {
+        let dir = std::env::temp_dir().join(format!("pd-identical-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("file.bin");
+        fs::write(&path, b"12345").unwrap();
+        let info = |total_size: u64, last_modified: Option<&str>| ResourceInfo {
+            url: url::Url::parse("https://example.com/file.bin").unwrap(),
+            supports_range: true,
+            total_size: Some(total_size),
+            etag: None,
+            last_modified: last_modified.map(str::to_string),
+            filename: None,
+            content_type: None,
+        };
+        let old = Some("Wed, 21 Oct 2015 07:28:00 GMT");
+        assert!(is_identical(&path, &info(5, old), None).unwrap());
+        assert!(!is_identical(&path, &info(6, old), None).unwrap());
+        assert!(!is_identical(&path, &info(5, Some("Fri, 01 Jan 2100 00:00:00 GMT")), None).unwrap());
+        assert!(!is_identical(&path, &info(5, None), None).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,454 @@
+//! # Progress Reporting
+//!
+//! This module lets callers follow a download while it runs. Workers count the bytes
+//! they write into a shared `Reporter`, which periodically hands a `Progress` snapshot
+//! (per-part and total byte counts, throughput and ETA) to a `ProgressObserver`.
+//! `TerminalProgress` is the observer used by the command line interface.
+//!
+//! ## Features
+//! - Observer trait with start, progress and finish events.
+//! - Throughput averaged over a sliding window, and an ETA when the size is known.
+//! - Throttles events so that observers are not called for every chunk.
+//! - Renders per-connection bars and the aggregate speed in a terminal.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::state::PartState;
+
+/// Shortest time between two `on_progress` events.
+pub const REPORT_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How far back throughput is averaged.
+const SPEED_WINDOW: Duration = Duration::from_secs(5);
+
+/// Progress of a single part.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartProgress {
+    /// Identifier for the part.
+    pub part_number: usize,
+    /// Bytes of the part written so far, including any written before a resume.
+    pub received: u64,
+    /// Size of the part, if known.
+    pub size: Option<u64>,
+}
+
+/// A snapshot of the progress of a download.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Progress {
+    /// Progress of every part, in the order the parts were created. Parts split off for
+    /// idle connections are added at the end.
+    pub parts: Vec<PartProgress>,
+    /// Progress of the part each connection is working on, by connection. Empty for a
+    /// single stream.
+    pub connections: Vec<PartProgress>,
+    /// Bytes written so far, over all parts.
+    pub downloaded: u64,
+    /// Size of the file, if known.
+    pub total: Option<u64>,
+    /// Time since the download started.
+    pub elapsed: Duration,
+    /// Recent throughput in bytes per second.
+    pub bytes_per_second: f64,
+    /// Estimated time until the download completes, if the size and speed are known.
+    pub eta: Option<Duration>,
+}
+
+/// Receives progress events from a running download.
+///
+/// Events are delivered from worker threads, one at a time. Every method has an empty
+/// default implementation, so observers only implement what they need.
+pub trait ProgressObserver: Send + Sync {
+    /// Called once before any data is transferred.
+    fn on_start(&self, _progress: &Progress) {}
+
+    /// Called as data arrives, at most once per `REPORT_INTERVAL`.
+    fn on_progress(&self, _progress: &Progress) {}
+
+    /// Called once when the transfer has ended, successfully or not.
+    fn on_finish(&self, _progress: &Progress) {}
+}
+
+/// Counts the bytes written by the workers of a download and reports them to an
+/// observer.
+pub struct Reporter {
+    observer: Option<Arc<dyn ProgressObserver>>,
+    parts: Mutex<Vec<PartProgress>>,
+    /// The index of the part each connection is working on.
+    connections: Mutex<Vec<Option<usize>>>,
+    total: Option<u64>,
+    started: Instant,
+    samples: Mutex<VecDeque<(Instant, u64)>>,
+}
+
+impl Reporter {
+    /// Creates a reporter for a download split into `parts`.
+    ///
+    /// # Parameters
+    /// - `observer`: Where events are sent; with `None` bytes are only counted.
+    /// - `parts`: The parts of the download and how much of each is already written.
+    /// - `total`: Size of the file, if known.
+    pub fn new(observer: Option<Arc<dyn ProgressObserver>>, parts: &[PartState], total: Option<u64>) -> Self {
+        let parts = parts
+            .iter()
+            .enumerate()
+            .map(|(part_number, part)| PartProgress {
+                part_number,
+                received: part.received,
+                size: Some(part.size()),
+            })
+            .collect();
+        Self::with_parts(observer, parts, total)
+    }
+
+    /// Creates a reporter for a download over a single stream of possibly unknown size.
+    pub fn single(observer: Option<Arc<dyn ProgressObserver>>, total: Option<u64>) -> Self {
+        let part = PartProgress { part_number: 0, received: 0, size: total };
+        Self::with_parts(observer, vec![part], total)
+    }
+
+    fn with_parts(observer: Option<Arc<dyn ProgressObserver>>, parts: Vec<PartProgress>, total: Option<u64>) -> Self {
+        let now = Instant::now();
+        let downloaded = parts.iter().map(|part| part.received).sum();
+        Self {
+            observer,
+            parts: Mutex::new(parts),
+            connections: Mutex::new(Vec::new()),
+            total,
+            started: now,
+            samples: Mutex::new(VecDeque::from([(now, downloaded)])),
+        }
+    }
+
+    /// Sends the `on_start` event.
+    pub fn start(&self) {
+        if let Some(observer) = &self.observer {
+            let samples = self.samples.lock().unwrap();
+            observer.on_start(&self.snapshot(&samples));
+        }
+    }
+
+    /// Records that `bytes` more bytes of part `index` have been written, and sends an
+    /// `on_progress` event if the last one is at least `REPORT_INTERVAL` old.
+    pub fn record(&self, index: usize, bytes: u64) {
+        self.parts.lock().unwrap()[index].received += bytes;
+        let Some(observer) = &self.observer else {
+            return;
+        };
+        // Whoever holds the lock is already reporting; this update shows up next time.
+        let Ok(mut samples) = self.samples.try_lock() else {
+            return;
+        };
+        let now = Instant::now();
+        if samples.back().is_some_and(|(at, _)| now.duration_since(*at) < REPORT_INTERVAL) {
+            return;
+        }
+        samples.push_back((now, self.downloaded()));
+        while samples.len() > 2 && samples.front().is_some_and(|(at, _)| now.duration_since(*at) > SPEED_WINDOW) {
+            samples.pop_front();
+        }
+        observer.on_progress(&self.snapshot(&samples));
+    }
+
+    /// Forgets what has been recorded for part `index`, when it is started over.
+    pub fn restart(&self, index: usize) {
+        self.parts.lock().unwrap()[index].received = 0;
+    }
+
+    /// Records that part `index` now ends after `size` bytes and that the rest of it,
+    /// `new_size` bytes, became a new part at the end of the list.
+    pub fn split(&self, index: usize, size: u64, new_size: u64) {
+        let mut parts = self.parts.lock().unwrap();
+        parts[index].size = Some(size);
+        let part_number = parts.len();
+        parts.push(PartProgress { part_number, received: 0, size: Some(new_size) });
+    }
+
+    /// Records that `connection` now works on part `index`, e.g. one it split off a slower
+    /// connection.
+    pub fn assign(&self, connection: usize, index: usize) {
+        let mut connections = self.connections.lock().unwrap();
+        if connections.len() <= connection {
+            connections.resize(connection + 1, None);
+        }
+        connections[connection] = Some(index);
+    }
+
+    /// Sends the `on_finish` event.
+    pub fn finish(&self) {
+        if let Some(observer) = &self.observer {
+            let mut samples = self.samples.lock().unwrap();
+            samples.push_back((Instant::now(), self.downloaded()));
+            observer.on_finish(&self.snapshot(&samples));
+        }
+    }
+
+    /// Returns the current progress.
+    pub fn progress(&self) -> Progress {
+        self.snapshot(&self.samples.lock().unwrap())
+    }
+
+    fn downloaded(&self) -> u64 {
+        self.parts.lock().unwrap().iter().map(|part| part.received).sum()
+    }
+
+    fn snapshot(&self, samples: &VecDeque<(Instant, u64)>) -> Progress {
+        let parts = self.parts.lock().unwrap().clone();
+        let connections = self
+            .connections
+            .lock()
+            .unwrap()
+            .iter()
+            .flatten()
+            .map(|&index| parts[index].clone())
+            .collect();
+        let downloaded = parts.iter().map(|part| part.received).sum();
+
+        let bytes_per_second = match (samples.front(), samples.back()) {
+            (Some((first_at, first)), Some((last_at, last))) if last_at > first_at => {
+                last.saturating_sub(*first) as f64 / last_at.duration_since(*first_at).as_secs_f64()
+            }
+            _ => 0.0,
+        };
+        let eta = match self.total {
+            Some(total) if bytes_per_second > 0.0 => {
+                Some(Duration::from_secs_f64(total.saturating_sub(downloaded) as f64 / bytes_per_second))
+            }
+            _ => None,
+        };
+
+        Progress {
+            parts,
+            connections,
+            downloaded,
+            total: self.total,
+            elapsed: self.started.elapsed(),
+            bytes_per_second,
+            eta,
+        }
+    }
+}
+
+/// Width of a progress bar in characters.
+const BAR_WIDTH: usize = 30;
+
+/// Draws a bar per connection and a summary line on standard error, redrawing them in
+/// place as the download progresses.
+#[derive(Default)]
+pub struct TerminalProgress {
+    lines_drawn: Mutex<usize>,
+}
+
+impl TerminalProgress {
+    /// Creates a renderer that has not drawn anything yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn draw(&self, progress: &Progress) {
+        // One line per connection, so the drawing keeps its height when parts are split.
+        let mut lines = Vec::new();
+        if progress.connections.len() > 1 {
+            for (connection, part) in progress.connections.iter().enumerate() {
+                lines.push(format!(
+                    "  #{:<2} {} {}",
+                    connection,
+                    bar(part.received, part.size),
+                    amount(part.received, part.size)
+                ));
+            }
+        }
+        let mut summary = format!(
+            "  {} {} at {}/s",
+            bar(progress.downloaded, progress.total),
+            amount(progress.downloaded, progress.total),
+            format_bytes(progress.bytes_per_second as u64)
+        );
+        if let Some(eta) = progress.eta {
+            summary.push_str(&format!(", ETA {}", format_duration(eta)));
+        }
+        lines.push(summary);
+
+        let mut drawn = self.lines_drawn.lock().unwrap();
+        let mut stderr = io::stderr().lock();
+        let mut output = String::new();
+        if *drawn > 0 {
+            // Move back to the first line of the previous drawing.
+            output.push_str(&format!("\x1b[{}A", drawn));
+        }
+        for line in &lines {
+            output.push_str("\r\x1b[2K");
+            output.push_str(line);
+            output.push('\n');
+        }
+        let _ = stderr.write_all(output.as_bytes());
+        let _ = stderr.flush();
+        *drawn = lines.len();
+    }
+}
+
+impl ProgressObserver for TerminalProgress {
+    fn on_start(&self, progress: &Progress) {
+        self.draw(progress);
+    }
+
+    fn on_progress(&self, progress: &Progress) {
+        self.draw(progress);
+    }
+
+    fn on_finish(&self, progress: &Progress) {
+        self.draw(progress);
+    }
+}
+
+/// Renders `[#####-----] 50%`, or a bar without a percentage when `size` is unknown.
+fn bar(received: u64, size: Option<u64>) -> String {
+    match size {
+        Some(size) => {
+            let fraction = if size == 0 { 1.0 } else { (received as f64 / size as f64).min(1.0) };
+            let filled = (fraction * BAR_WIDTH as f64) as usize;
+            format!(
+                "[{}{}] {:>5.1}%",
+                "#".repeat(filled),
+                "-".repeat(BAR_WIDTH - filled),
+                fraction * 100.0
+            )
+        }
+        None => format!("[{}]    ? ", "?".repeat(BAR_WIDTH)),
+    }
+}
+
+fn amount(received: u64, size: Option<u64>) -> String {
+    match size {
+        Some(size) => format!("{} / {}", format_bytes(received), format_bytes(size)),
+        None => format_bytes(received),
+    }
+}
+
+/// Formats a byte count with a binary unit, e.g. `1.5 MiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Formats a duration as `m:ss`, or `h:mm:ss` from an hour on.
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_bytes, format_duration, Progress, ProgressObserver, Reporter, REPORT_INTERVAL};
+    use crate::state::PartState;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<(&'static str, Progress)>>,
+    }
+
+    impl ProgressObserver for Recorder {
+        fn on_start(&self, progress: &Progress) {
+            self.events.lock().unwrap().push(("start", progress.clone()));
+        }
+
+        fn on_progress(&self, progress: &Progress) {
+            self.events.lock().unwrap().push(("progress", progress.clone()));
+        }
+
+        fn on_finish(&self, progress: &Progress) {
+            self.events.lock().unwrap().push(("finish", progress.clone()));
+        }
+    }
+
+    #[test]
+    fn test_reporter_counts_parts_and_throttles_events() {
+        let recorder = Arc::new(Recorder::default());
+        let parts = [
+            PartState { start: 0, end: 99, received: 40 },
+            PartState { start: 100, end: 199, received: 0 },
+        ];
+        let reporter = Reporter::new(Some(recorder.clone()), &parts, Some(200));
+        reporter.start();
+        reporter.record(1, 10);
+        reporter.record(1, 10);
+        thread::sleep(REPORT_INTERVAL);
+        reporter.record(0, 60);
+        reporter.finish();
+
+        let events = recorder.events.lock().unwrap();
+        let kinds: Vec<&str> = events.iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(kinds, vec!["start", "progress", "finish"]);
+
+        let (_, last) = events.last().unwrap();
+        assert_eq!(last.downloaded, 120);
+        assert_eq!(last.parts[0].received, 100);
+        assert_eq!(last.parts[1].received, 20);
+        assert!(last.bytes_per_second > 0.0);
+        assert!(last.eta.is_some());
+    }
+
+    #[test]
+    fn test_connections_follow_split_parts() {
+        let parts = [
+            PartState { start: 0, end: 99, received: 100 },
+            PartState { start: 100, end: 199, received: 20 },
+        ];
+        let reporter = Reporter::new(None, &parts, Some(200));
+        reporter.assign(1, 1);
+        reporter.assign(0, 0);
+        assert_eq!(reporter.progress().connections.len(), 2);
+
+        // Connection 0 finished its part and takes over the second half of part 1.
+        reporter.split(1, 60, 40);
+        reporter.assign(0, 2);
+        reporter.record(2, 15);
+        let progress = reporter.progress();
+        assert_eq!(progress.parts.len(), 3);
+        assert_eq!(progress.connections.len(), 2);
+        assert_eq!((progress.connections[0].part_number, progress.connections[0].received), (2, 15));
+        assert_eq!(progress.connections[0].size, Some(40));
+        assert_eq!(progress.connections[1].size, Some(60));
+        assert_eq!(progress.downloaded, 135);
+    }
+
+    #[test]
+    fn test_single_stream_of_unknown_size() {
+        let reporter = Reporter::single(None, None);
+        reporter.record(0, 5);
+        reporter.restart(0);
+        reporter.record(0, 3);
+        let progress = reporter.progress();
+        assert_eq!(progress.downloaded, 3);
+        assert_eq!(progress.parts[0].size, None);
+        assert_eq!(progress.total, None);
+        assert_eq!(progress.eta, None);
+    }
+
+    #[test]
+    fn test_formatting() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1024), "5.0 GiB");
+        assert_eq!(format_duration(Duration::from_secs(75)), "1:15");
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1:02:05");
+    }
+}
@@ -0,0 +1,155 @@
+//! # Request Headers
+//!
+//! This module holds the request headers that are configurable: the `User-Agent`, the
+//! `Referer` and any number of extra headers. They are sent with the `HEAD` probe and
+//! with every `GET` request, next to the headers the downloader manages itself.
+//!
+//! ## Features
+//! - Configurable `User-Agent`, with a default naming this downloader.
+//! - Optional `Referer`.
+//! - Extra headers given as `Name: value`.
+//! - Merges an extra `Cookie` header with the cookies from the cookie jar.
+//! - Rejects names and values that would break the request.
+
+use crate::error::DownloaderError;
+
+/// The `User-Agent` sent when none is configured.
+pub const DEFAULT_USER_AGENT: &str = "rust-downloader/1.0";
+
+/// Headers the downloader sets itself, which cannot be given as extra headers.
+const MANAGED_HEADERS: [&str; 5] = ["host", "range", "connection", "accept-encoding", "authorization"];
+
+/// The configurable headers of every request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestHeaders {
+    /// The `User-Agent` header. Defaults to `DEFAULT_USER_AGENT`.
+    pub user_agent: String,
+    /// The `Referer` header, if any. Defaults to `None`.
+    pub referer: Option<String>,
+    /// Further headers, sent in order. Defaults to none.
+    pub extra: Vec<(String, String)>,
+}
+
+impl Default for RequestHeaders {
+    fn default() -> Self {
+        Self {
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            referer: None,
+            extra: Vec::new(),
+        }
+    }
+}
+
+impl RequestHeaders {
+    /// Renders the headers as request lines, each ending in `\r\n`.
+    ///
+    /// An extra header named `User-Agent` or `Referer` replaces the configured one. Extra
+    /// `Cookie` headers and `cookies` are sent together on one `Cookie` line.
+    ///
+    /// # Parameters
+    /// - `cookies`: The cookies from the cookie jar for this request, if any.
+    pub fn to_lines(&self, cookies: Option<&str>) -> String {
+        let overridden = |name: &str| self.extra.iter().any(|(extra, _)| extra.eq_ignore_ascii_case(name));
+        let mut lines = String::new();
+        if !overridden("user-agent") {
+            lines.push_str(&format!("User-Agent: {}\r\n", self.user_agent));
+        }
+        if let Some(referer) = self.referer.as_ref().filter(|_| !overridden("referer")) {
+            lines.push_str(&format!("Referer: {}\r\n", referer));
+        }
+        let mut cookie_values = Vec::new();
+        for (name, value) in &self.extra {
+            if name.eq_ignore_ascii_case("cookie") {
+                cookie_values.push(value.as_str());
+            } else {
+                lines.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        cookie_values.extend(cookies);
+        if !cookie_values.is_empty() {
+            lines.push_str(&format!("Cookie: {}\r\n", cookie_values.join("; ")));
+        }
+        lines
+    }
+}
+
+/// Parses an extra header given as `Name: value`.
+///
+/// # Errors
+/// Returns `DownloaderError::UserInputError` if the name is not a valid header name, the
+/// value contains a line break, or the header is one the downloader sets itself
+/// (`Host`, `Range`, `Connection`, `Accept-Encoding` and `Authorization`). A `Cookie`
+/// header is sent together with the cookies from the cookie jar, if there is one.
+pub fn parse_header(value: &str) -> Result<(String, String), DownloaderError> {
+    let invalid = |reason: &str| DownloaderError::UserInputError(format!("Invalid header '{}': {}", value, reason));
+    let (name, header_value) = value.split_once(':').ok_or_else(|| invalid("expected 'Name: value'"))?;
+    let (name, header_value) = (name.trim(), header_value.trim());
+    let is_token = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    if name.is_empty() || !name.chars().all(is_token) {
+        return Err(invalid("not a valid header name"));
+    }
+    if MANAGED_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+        return Err(invalid("this header is set by the downloader"));
+    }
+    Ok((name.to_string(), parse_value(header_value)?))
+}
+
+/// Checks that `value` can be sent as a header value, e.g. a `User-Agent`.
+///
+/// # Errors
+/// Returns `DownloaderError::UserInputError` if the value contains a line break, which
+/// would end the header early.
+pub fn parse_value(value: &str) -> Result<String, DownloaderError> {
+    if value.contains(['\r', '\n']) {
+        return Err(DownloaderError::UserInputError(format!(
+            "Invalid header value '{}': it contains a line break",
+            value.escape_debug()
+        )));
+    }
+    Ok(value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_header, parse_value, RequestHeaders};
+
+    #[test]
+    fn test_headers_to_lines() {
+        let mut headers = RequestHeaders::default();
+        assert_eq!(headers.to_lines(None), "User-Agent: rust-downloader/1.0\r\n");
+
+        headers.referer = Some("https://example.com/".into());
+        headers.extra.push(("X-Api-Key".into(), "abc".into()));
+        assert_eq!(
+            headers.to_lines(None),
+            "User-Agent: rust-downloader/1.0\r\nReferer: https://example.com/\r\nX-Api-Key: abc\r\n"
+        );
+
+        headers.extra.push(("user-agent".into(), "curl/8.0".into()));
+        assert!(!headers.to_lines(None).contains("rust-downloader"));
+    }
+
+    #[test]
+    fn test_cookie_header_merges_with_jar() {
+        let mut headers = RequestHeaders::default();
+        assert!(headers.to_lines(Some("session=abc")).ends_with("\r\nCookie: session=abc\r\n"));
+
+        headers.extra.push(parse_header("Cookie: theme=dark").unwrap());
+        assert!(headers.to_lines(None).ends_with("\r\nCookie: theme=dark\r\n"));
+        let lines = headers.to_lines(Some("session=abc"));
+        assert!(lines.ends_with("\r\nCookie: theme=dark; session=abc\r\n"));
+        assert_eq!(lines.matches("Cookie:").count(), 1);
+    }
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(parse_header("X-Api-Key:  abc ").unwrap(), ("X-Api-Key".into(), "abc".into()));
+        assert_eq!(parse_header("Accept: */*").unwrap(), ("Accept".into(), "*/*".into()));
+        assert!(parse_header("no colon").is_err());
+        assert!(parse_header("Bad Name: x").is_err());
+        assert!(parse_header("X-Evil: a\r\nHost: b").is_err());
+        assert!(parse_header("range: bytes=0-1").is_err());
+        assert_eq!(parse_value("Mozilla/5.0").unwrap(), "Mozilla/5.0");
+        assert!(parse_value("a\nb").is_err());
+    }
+}
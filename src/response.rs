@@ -0,0 +1,315 @@
+//! # HTTP Response Parsing
+//!
+//! This module parses the head of an HTTP/1.1 response: the status line and the header
+//! fields. It is shared by the `HEAD` probe and the ranged `GET` requests so that both
+//! validate status codes and read headers the same way.
+//!
+//! ## Features
+//! - Parses the status line into version, status code and reason phrase.
+//! - Stores headers case-insensitively, keeping repeated fields.
+//! - Limits the size of the response head.
+//! - Parses `Content-Length` and `Content-Range` values.
+
+use std::io::Read;
+use crate::error::DownloaderError;
+
+/// Largest response head (status line and headers) that will be accepted.
+pub const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// Largest number of header fields that will be accepted.
+pub const MAX_HEADERS: usize = 128;
+
+/// The header fields of a response, in the order they were received.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Headers {
+    fields: Vec<(String, String)>,
+}
+
+impl Headers {
+    /// Returns the value of the first field named `name`, ignoring case.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the values of every field named `name`, ignoring case.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether any field named `name` contains `token` in its comma-separated list.
+    pub fn contains_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// Iterates over all fields as `(name, value)` pairs.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Appends a field.
+    pub fn push(&mut self, name: &str, value: &str) {
+        self.fields.push((name.to_string(), value.to_string()));
+    }
+}
+
+/// A parsed `Content-Range: bytes <start>-<end>/<total>` value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContentRange {
+    /// First byte of the range.
+    pub start: u64,
+    /// Last byte of the range (inclusive).
+    pub end: u64,
+    /// Size of the complete resource, if the server knows it.
+    pub total: Option<u64>,
+}
+
+/// The status line and headers of a response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Response {
+    /// The protocol version, e.g. `HTTP/1.1`.
+    pub version: String,
+    /// The status code.
+    pub status: u16,
+    /// The reason phrase, which may be empty.
+    pub reason: String,
+    /// The header fields.
+    pub headers: Headers,
+}
+
+impl Response {
+    /// Parses a response head.
+    ///
+    /// # Parameters
+    /// - `head`: The status line and header fields, with or without the final empty line.
+    ///
+    /// # Errors
+    /// Returns `DownloaderError::ResponseError` if the head is not a valid HTTP/1.x response.
+    pub fn parse(head: &[u8]) -> Result<Self, DownloaderError> {
+        let invalid = |message: &str| DownloaderError::ResponseError(message.to_string());
+
+        if head.len() > MAX_HEAD_SIZE {
+            return Err(invalid("Response headers are too large"));
+        }
+        // Header values are usually ASCII; tolerate stray Latin-1 bytes rather than fail.
+        let head = String::from_utf8_lossy(head);
+        let mut lines = head.split('\n').map(|line| line.strip_suffix('\r').unwrap_or(line));
+
+        let status_line = lines.next().unwrap_or_default();
+        let mut fields = status_line.splitn(3, ' ');
+        let version = fields.next().unwrap_or_default();
+        if !version.starts_with("HTTP/1.") {
+            return Err(invalid(&format!("Invalid status line: {}", status_line)));
+        }
+        let status = fields
+            .next()
+            .filter(|code| code.len() == 3)
+            .and_then(|code| code.parse::<u16>().ok())
+            .filter(|code| (100..600).contains(code))
+            .ok_or_else(|| invalid(&format!("Invalid status code in: {}", status_line)))?;
+        let reason = fields.next().unwrap_or_default().to_string();
+
+        let mut headers = Headers::default();
+        for line in lines {
+            if line.is_empty() {
+                break;
+            }
+            if line.starts_with([' ', '\t']) {
+                // Obsolete line folding continues the previous field's value.
+                let (_, value) = headers
+                    .fields
+                    .last_mut()
+                    .ok_or_else(|| invalid("Header continuation without a header"))?;
+                value.push(' ');
+                value.push_str(line.trim());
+                continue;
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid(&format!("Malformed header line: {}", line)))?;
+            if name.is_empty() || name.contains(|c: char| c.is_whitespace()) {
+                return Err(invalid(&format!("Malformed header name: {}", name)));
+            }
+            if headers.fields.len() == MAX_HEADERS {
+                return Err(invalid("Too many response headers"));
+            }
+            headers.push(name, value.trim());
+        }
+
+        Ok(Self {
+            version: version.to_string(),
+            status,
+            reason,
+            headers,
+        })
+    }
+
+    /// Whether the status code is in the `2xx` range.
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Returns the `Content-Length`, if present.
+    ///
+    /// # Errors
+    /// Returns a `DownloaderError` if the header is not a number, or appears several
+    /// times with different values.
+    pub fn content_length(&self) -> Result<Option<u64>, DownloaderError> {
+        let mut length = None;
+        for value in self.headers.get_all("content-length") {
+            let parsed = value.trim().parse::<u64>().map_err(|_| {
+                DownloaderError::ResponseError("Invalid Content-Length header".into())
+            })?;
+            if length.is_some_and(|length| length != parsed) {
+                return Err(DownloaderError::ResponseError("Conflicting Content-Length headers".into()));
+            }
+            length = Some(parsed);
+        }
+        Ok(length)
+    }
+
+    /// Returns the `Content-Range`, if present.
+    ///
+    /// # Errors
+    /// Returns a `DownloaderError` if the header is present but malformed.
+    pub fn content_range(&self) -> Result<Option<ContentRange>, DownloaderError> {
+        self.headers
+            .get("content-range")
+            .map(|value| {
+                parse_content_range(value).ok_or_else(|| {
+                    DownloaderError::ResponseError(format!("Invalid Content-Range header: {}", value))
+                })
+            })
+            .transpose()
+    }
+}
+
+/// Parses a `Content-Range` value of the form `bytes <start>-<end>/<total or *>`.
+pub fn parse_content_range(value: &str) -> Option<ContentRange> {
+    let range = value.trim().strip_prefix("bytes ")?;
+    let (span, total) = range.split_once('/')?;
+    let (start, end) = span.split_once('-')?;
+    let (start, end) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+    let total = match total.trim() {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+    if start > end || total.is_some_and(|total| end >= total) {
+        return None;
+    }
+    Some(ContentRange { start, end, total })
+}
+
+/// Reads a response head from `reader`.
+///
+/// # Returns
+/// The parsed response, and any bytes read past the head (the start of the body).
+///
+/// # Errors
+/// Returns a `DownloaderError` if the connection closes before the head is complete,
+/// the head exceeds `MAX_HEAD_SIZE` or it cannot be parsed.
+pub fn read_response_head<R: Read>(reader: &mut R) -> Result<(Response, Vec<u8>), DownloaderError> {
+    let mut buffer = [0u8; 8192];
+    let mut head = Vec::new();
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            return Err(DownloaderError::IoError(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "Connection closed before the response headers were complete",
+            )));
+        }
+
+        // Only search the new bytes, plus three bytes of overlap with earlier data.
+        let search_from = head.len().saturating_sub(3);
+        head.extend_from_slice(&buffer[..n]);
+        if let Some(pos) = head[search_from..].windows(4).position(|w| w == b"\r\n\r\n") {
+            let body_start = search_from + pos + 4;
+            let body = head.split_off(body_start);
+            return Ok((Response::parse(&head)?, body));
+        }
+        if head.len() > MAX_HEAD_SIZE {
+            return Err(DownloaderError::ResponseError("Response headers are too large".into()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_content_range, read_response_head, ContentRange, Response, MAX_HEAD_SIZE};
+    use crate::error::DownloaderError;
+
+    #[test]
+    fn test_parse_status_line_and_headers() {
+        let head = b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 0-9/100\r\n\
+                     content-length: 10\r\nSet-Cookie: a=1\r\nSET-COOKIE: b=2\r\n\r\n";
+        let response = Response::parse(head).unwrap();
+        assert_eq!(response.version, "HTTP/1.1");
+        assert_eq!(response.status, 206);
+        assert_eq!(response.reason, "Partial Content");
+        assert_eq!(response.headers.get("Content-Length"), Some("10"));
+        assert_eq!(response.headers.get_all("set-cookie").collect::<Vec<_>>(), vec!["a=1", "b=2"]);
+        assert_eq!(response.content_length().unwrap(), Some(10));
+        assert_eq!(
+            response.content_range().unwrap(),
+            Some(ContentRange { start: 0, end: 9, total: Some(100) })
+        );
+    }
+
+    #[test]
+    fn test_header_tokens_and_folding() {
+        let head = b"HTTP/1.0 200 OK\r\nAccept-Ranges: none, Bytes\r\nX-Long: first\r\n  second\r\n\r\n";
+        let response = Response::parse(head).unwrap();
+        assert!(response.headers.contains_token("accept-ranges", "bytes"));
+        assert_eq!(response.headers.get("x-long"), Some("first second"));
+    }
+
+    #[test]
+    fn test_invalid_responses() {
+        assert!(Response::parse(b"SSH-2.0-OpenSSH\r\n\r\n").is_err());
+        assert!(Response::parse(b"HTTP/1.1 20 OK\r\n\r\n").is_err());
+        assert!(Response::parse(b"HTTP/1.1 200 OK\r\nno colon here\r\n\r\n").is_err());
+        let conflicting = Response::parse(b"HTTP/1.1 200 OK\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n").unwrap();
+        assert!(conflicting.content_length().is_err());
+    }
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(
+            parse_content_range("bytes 100-199/*"),
+            Some(ContentRange { start: 100, end: 199, total: None })
+        );
+        assert_eq!(parse_content_range("bytes 200-100/300"), None);
+        assert_eq!(parse_content_range("bytes 0-300/300"), None);
+        assert_eq!(parse_content_range("bytes */300"), None);
+        assert_eq!(parse_content_range("items 0-1/2"), None);
+    }
+
+    #[test]
+    fn test_read_response_head_returns_body_prefix() {
+        let mut data: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+        let (response, body) = read_response_head(&mut data).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(body, b"hello");
+    }
+
+    #[test]
+    fn test_read_response_head_limits_size() {
+        let mut data = b"HTTP/1.1 200 OK\r\n".to_vec();
+        while data.len() <= MAX_HEAD_SIZE {
+            data.extend_from_slice(b"X-Filler: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\n");
+        }
+        let result = read_response_head(&mut data.as_slice());
+        assert!(matches!(result, Err(DownloaderError::ResponseError(_))));
+
+        let result = read_response_head(&mut &b"HTTP/1.1 200 OK\r\n"[..]);
+        assert!(matches!(result, Err(DownloaderError::IoError(_))));
+    }
+}
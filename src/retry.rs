@@ -0,0 +1,119 @@
+//! # Retry Policy
+//!
+//! This module decides whether a failed part download should be attempted again and how
+//! long to wait before doing so. Delays grow exponentially with each attempt and are
+//! jittered so that parts failing together do not retry in lockstep.
+//!
+//! ## Features
+//! - Classifies errors as retryable (connection resets, timeouts, 5xx) or fatal.
+//! - Computes exponential backoff delays with jitter.
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::io;
+use std::time::Duration;
+use crate::error::DownloaderError;
+
+/// How often and how patiently a part download is retried.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt; `0` disables retrying.
+    pub max_retries: u32,
+    /// Delay before the first retry; doubled for every further retry.
+    pub base_delay: Duration,
+    /// Upper bound for the delay between two attempts.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns how long to wait before retry number `attempt` (starting at 1).
+    ///
+    /// The delay is `base_delay * 2^(attempt - 1)`, capped at `max_delay`, and then
+    /// randomly reduced by up to half to spread out concurrent retries.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self.base_delay.saturating_mul(factor).min(self.max_delay);
+
+        let half = delay / 2;
+        let jitter_range = half.as_nanos().max(1) as u64;
+        let jitter = RandomState::new().hash_one(attempt) % jitter_range;
+        half + Duration::from_nanos(jitter)
+    }
+}
+
+/// Whether a failed part download is worth retrying.
+///
+/// Connection failures, resets, unexpected EOFs, timeouts and `408`, `429` and `5xx`
+/// responses are retryable; anything else (e.g. `404` or `416`) is fatal.
+pub fn is_retryable(error: &DownloaderError) -> bool {
+    match error {
+        DownloaderError::IoError(e) => matches!(
+            e.kind(),
+            io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::UnexpectedEof
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::WouldBlock
+                | io::ErrorKind::Interrupted
+        ),
+        DownloaderError::ConnectionError(_)
+        | DownloaderError::DnsError(_)
+        | DownloaderError::Timeout(_) => true,
+        DownloaderError::HttpStatus(status, _) => {
+            matches!(status, 408 | 429) || (500..600).contains(status)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_retryable, RetryPolicy};
+    use crate::error::DownloaderError;
+    use std::io;
+    use std::time::Duration;
+
+    #[test]
+    fn test_delay_grows_exponentially_with_jitter() {
+        let policy = RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+        };
+        for (attempt, full) in [(1, 100), (2, 200), (3, 400), (4, 800), (5, 1000), (9, 1000)] {
+            let delay = policy.delay(attempt);
+            assert!(delay >= Duration::from_millis(full / 2), "attempt {}: {:?}", attempt, delay);
+            assert!(delay <= Duration::from_millis(full), "attempt {}: {:?}", attempt, delay);
+        }
+    }
+
+    #[test]
+    fn test_error_classification() {
+        let reset = io::Error::new(io::ErrorKind::ConnectionReset, "reset");
+        assert!(is_retryable(&DownloaderError::IoError(reset)));
+        let eof = io::Error::new(io::ErrorKind::UnexpectedEof, "eof");
+        assert!(is_retryable(&DownloaderError::IoError(eof)));
+        assert!(is_retryable(&DownloaderError::ConnectionError("refused".into())));
+        assert!(is_retryable(&DownloaderError::Timeout("read".into())));
+        assert!(is_retryable(&DownloaderError::HttpStatus(503, "Service Unavailable".into())));
+        assert!(is_retryable(&DownloaderError::HttpStatus(429, "Too Many Requests".into())));
+
+        assert!(!is_retryable(&DownloaderError::HttpStatus(404, "Not Found".into())));
+        assert!(!is_retryable(&DownloaderError::HttpStatus(416, "Range Not Satisfiable".into())));
+        let denied = io::Error::new(io::ErrorKind::PermissionDenied, "denied");
+        assert!(!is_retryable(&DownloaderError::IoError(denied)));
+        assert!(!is_retryable(&DownloaderError::FileError("disk full".into())));
+    }
+}
@@ -0,0 +1,430 @@
+//! # Download State
+//!
+//! This module persists the progress of a download in a small control file next to the
+//! output, so that an interrupted download can be continued instead of restarted. The
+//! control file records the URL, the id that names the download's part files, the total
+//! size, the server's validators (`ETag` and `Last-Modified`) and how many bytes of each
+//! part have been written.
+//!
+//! ## Features
+//! - Saves and loads the control file in a simple line-based format.
+//! - Refuses to resume when the remote file has changed.
+//! - Tracks per-part progress while workers are writing.
+//! - Splits the largest remaining part so that idle workers can take over half of it.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::error::DownloaderError;
+use crate::http::ResourceInfo;
+use crate::temp;
+
+/// How often the control file is rewritten while a download is running.
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Progress of a single part.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartState {
+    /// Starting byte of the part.
+    pub start: u64,
+    /// Ending byte of the part (inclusive).
+    pub end: u64,
+    /// Number of bytes already written, counted from `start`.
+    pub received: u64,
+}
+
+impl PartState {
+    /// Length of the part in bytes.
+    pub fn size(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Whether every byte of the part has been written.
+    pub fn is_complete(&self) -> bool {
+        self.received >= self.size()
+    }
+}
+
+/// Everything needed to continue a download.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DownloadState {
+    /// The URL being downloaded, with any password redacted by `auth::redact`.
+    pub url: String,
+    /// The id of the download, which part files are named after; see `temp::part_path`.
+    pub id: String,
+    /// Total size of the file.
+    pub total_size: u64,
+    /// The server's `ETag` for the file, if any.
+    pub etag: Option<String>,
+    /// The server's `Last-Modified` date for the file, if any.
+    pub last_modified: Option<String>,
+    /// Progress of every part, in file order.
+    pub parts: Vec<PartState>,
+}
+
+/// Returns the path of the control file for an output file.
+///
+/// # Parameters
+/// - `output_file`: The final name of the output file.
+///
+/// # Returns
+/// The output path with `.state` appended.
+pub fn control_path(output_file: &str) -> PathBuf {
+    PathBuf::from(format!("{}.state", output_file))
+}
+
+impl DownloadState {
+    /// Loads a control file.
+    ///
+    /// # Returns
+    /// `None` if the file does not exist, otherwise the parsed state.
+    ///
+    /// # Errors
+    /// Returns a `DownloaderError` if the file cannot be read or is malformed.
+    pub fn load(path: &Path) -> Result<Option<Self>, DownloaderError> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(path)?;
+        Self::parse(&contents).map(Some)
+    }
+
+    /// Writes the control file, replacing it atomically.
+    pub fn save(&self, path: &Path) -> Result<(), DownloaderError> {
+        let temp = PathBuf::from(format!("{}.tmp", path.display()));
+        fs::write(&temp, self.serialize())?;
+        fs::rename(&temp, path)?;
+        Ok(())
+    }
+
+    /// Checks that the state describes the same remote file as `info`.
+    ///
+    /// # Errors
+    /// Returns `DownloaderError::ResumeError` describing what changed.
+    pub fn validate(&self, url: &str, info: &ResourceInfo) -> Result<(), DownloaderError> {
+        if self.url != url {
+            return Err(DownloaderError::ResumeError(format!(
+                "control file belongs to a different URL ({})",
+                self.url
+            )));
+        }
+        match info.total_size {
+            Some(size) if size == self.total_size => {}
+            Some(size) => {
+                return Err(DownloaderError::ResumeError(format!(
+                    "remote size changed from {} to {} bytes",
+                    self.total_size, size
+                )))
+            }
+            None => return Err(DownloaderError::ResumeError("remote size is no longer known".into())),
+        }
+        if self.etag != info.etag {
+            return Err(DownloaderError::ResumeError("remote ETag changed".into()));
+        }
+        if self.last_modified != info.last_modified {
+            return Err(DownloaderError::ResumeError("remote Last-Modified changed".into()));
+        }
+        Ok(())
+    }
+
+    fn serialize(&self) -> String {
+        let mut out = format!("url {}\nid {}\ntotal_size {}\n", self.url, self.id, self.total_size);
+        if let Some(etag) = &self.etag {
+            out.push_str(&format!("etag {}\n", etag));
+        }
+        if let Some(last_modified) = &self.last_modified {
+            out.push_str(&format!("last_modified {}\n", last_modified));
+        }
+        for part in &self.parts {
+            out.push_str(&format!("part {} {} {}\n", part.start, part.end, part.received));
+        }
+        out
+    }
+
+    fn parse(contents: &str) -> Result<Self, DownloaderError> {
+        let malformed = |line: &str| {
+            DownloaderError::ResumeError(format!("malformed control file line: {}", line))
+        };
+
+        let mut url = None;
+        let mut id = None;
+        let mut total_size = None;
+        let mut etag = None;
+        let mut last_modified = None;
+        let mut parts = Vec::new();
+
+        for line in contents.lines().filter(|line| !line.is_empty()) {
+            let (key, value) = line.split_once(' ').ok_or_else(|| malformed(line))?;
+            match key {
+                "url" => url = Some(value.to_string()),
+                "id" => id = Some(value.to_string()),
+                "total_size" => total_size = Some(value.parse().map_err(|_| malformed(line))?),
+                "etag" => etag = Some(value.to_string()),
+                "last_modified" => last_modified = Some(value.to_string()),
+                "part" => {
+                    let numbers = value
+                        .split(' ')
+                        .map(|n| n.parse::<u64>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|_| malformed(line))?;
+                    match numbers[..] {
+                        [start, end, received] if start <= end => {
+                            parts.push(PartState { start, end, received })
+                        }
+                        _ => return Err(malformed(line)),
+                    }
+                }
+                _ => return Err(malformed(line)),
+            }
+        }
+
+        Ok(Self {
+            url: url.ok_or_else(|| malformed("missing url"))?,
+            // Control files written before ids existed get a new one; their part files
+            // are then not found and downloaded again.
+            id: id.unwrap_or_else(temp::new_download_id),
+            total_size: total_size.ok_or_else(|| malformed("missing total_size"))?,
+            etag,
+            last_modified,
+            parts,
+        })
+    }
+}
+
+/// Shares a `DownloadState` between workers and periodically persists it.
+///
+/// Besides counting written bytes, the tracker hands out new parts: a worker that has
+/// finished its own part can split the largest remaining range of another one.
+pub struct StateTracker {
+    shared: Mutex<Shared>,
+    path: Option<PathBuf>,
+    last_saved: Mutex<Instant>,
+}
+
+struct Shared {
+    state: DownloadState,
+    /// Bytes of each part that are being written but not yet recorded; see `claim`.
+    in_flight: Vec<u64>,
+}
+
+impl StateTracker {
+    /// Creates a tracker. When `path` is `None` progress is only kept in memory.
+    pub fn new(state: DownloadState, path: Option<PathBuf>) -> Self {
+        let in_flight = vec![0; state.parts.len()];
+        Self {
+            shared: Mutex::new(Shared { state, in_flight }),
+            path,
+            last_saved: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Reserves up to `len` bytes of part `index` for writing, and returns how many may
+    /// be written: fewer than `len`, or none, once the part has been shortened by a split.
+    ///
+    /// The reservation lasts until the next `record` for the part, so a concurrent split
+    /// never hands out bytes that are still being written.
+    pub fn claim(&self, index: usize, len: u64) -> u64 {
+        let mut shared = self.shared.lock().unwrap();
+        let part = &shared.state.parts[index];
+        let allowed = len.min(part.size().saturating_sub(part.received));
+        shared.in_flight[index] = allowed;
+        allowed
+    }
+
+    /// Records that `bytes` more bytes of part `index` have been written, ending any
+    /// reservation made with `claim`.
+    ///
+    /// The control file is rewritten at most once per `SAVE_INTERVAL`.
+    pub fn record(&self, index: usize, bytes: u64) -> Result<(), DownloaderError> {
+        {
+            let mut shared = self.shared.lock().unwrap();
+            shared.state.parts[index].received += bytes;
+            shared.in_flight[index] = 0;
+        }
+
+        let mut last_saved = self.last_saved.lock().unwrap();
+        if last_saved.elapsed() >= SAVE_INTERVAL {
+            *last_saved = Instant::now();
+            drop(last_saved);
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Splits the part with the most bytes left in two, if that leaves at least
+    /// `min_segment` bytes on each side.
+    ///
+    /// The part keeps the first half of its remaining range and a new part is added
+    /// for the second half.
+    ///
+    /// # Returns
+    /// The indexes of the part that was split and of the new part.
+    pub fn split_largest(&self, min_segment: u64) -> Option<(usize, usize)> {
+        let mut shared = self.shared.lock().unwrap();
+        let Shared { state, in_flight } = &mut *shared;
+        let (index, remaining) = state
+            .parts
+            .iter()
+            .zip(in_flight.iter())
+            .map(|(part, pending)| part.size().saturating_sub(part.received + pending))
+            .enumerate()
+            .max_by_key(|(_, remaining)| *remaining)?;
+        if remaining < 2 * min_segment.max(1) {
+            return None;
+        }
+
+        let part = &mut state.parts[index];
+        let split_at = part.end + 1 - remaining / 2;
+        let new_part = PartState { start: split_at, end: part.end, received: 0 };
+        part.end = split_at - 1;
+        state.parts.push(new_part);
+        in_flight.push(0);
+        Some((index, state.parts.len() - 1))
+    }
+
+    /// Returns how many bytes from the start of the file have been written without a
+    /// gap, i.e. the size of the complete parts before the first incomplete one plus
+    /// what has been written of that part.
+    pub fn contiguous(&self) -> u64 {
+        let mut parts = self.parts();
+        parts.sort_by_key(|part| part.start);
+        let mut written = 0;
+        for part in &parts {
+            written += part.received.min(part.size());
+            if !part.is_complete() {
+                break;
+            }
+        }
+        written
+    }
+
+    /// Returns the current progress of part `index`.
+    pub fn part(&self, index: usize) -> PartState {
+        self.shared.lock().unwrap().state.parts[index].clone()
+    }
+
+    /// Returns the current progress of every part, in the order the parts were created.
+    pub fn parts(&self) -> Vec<PartState> {
+        self.shared.lock().unwrap().state.parts.clone()
+    }
+
+    /// Writes the control file now, if one is configured.
+    pub fn save(&self) -> Result<(), DownloaderError> {
+        match &self.path {
+            Some(path) => self.shared.lock().unwrap().state.save(path),
+            None => Ok(()),
+        }
+    }
+
+    /// Deletes the control file once the download is complete.
+    pub fn remove(&self) -> Result<(), DownloaderError> {
+        match &self.path {
+            Some(path) if path.exists() => Ok(fs::remove_file(path)?),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{control_path, DownloadState, PartState, StateTracker};
+    use crate::error::DownloaderError;
+    use crate::http::ResourceInfo;
+    use url::Url;
+
+    fn sample_state() -> DownloadState {
+        DownloadState {
+            url: "https://example.com/file.bin".to_string(),
+            id: "0123456789ab".to_string(),
+            total_size: 1000,
+            etag: Some("\"abc123\"".to_string()),
+            last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
+            parts: vec![
+                PartState { start: 0, end: 499, received: 500 },
+                PartState { start: 500, end: 999, received: 120 },
+            ],
+        }
+    }
+
+    fn sample_info() -> ResourceInfo {
+        ResourceInfo {
+            url: Url::parse("https://example.com/file.bin").unwrap(),
+            supports_range: true,
+            total_size: Some(1000),
+            etag: Some("\"abc123\"".to_string()),
+            last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
+            filename: None,
+            content_type: None,
+        }
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let dir = std::env::temp_dir().join(format!("pd-state-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = control_path(dir.join("file.bin").to_str().unwrap());
+
+        assert_eq!(DownloadState::load(&path).unwrap(), None);
+        let state = sample_state();
+        state.save(&path).unwrap();
+        assert_eq!(DownloadState::load(&path).unwrap(), Some(state));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_validate_refuses_changed_validators() {
+        let state = sample_state();
+        assert!(state.validate("https://example.com/file.bin", &sample_info()).is_ok());
+
+        let mut info = sample_info();
+        info.etag = Some("\"def456\"".to_string());
+        let result = state.validate("https://example.com/file.bin", &info);
+        assert!(matches!(result, Err(DownloaderError::ResumeError(_))));
+
+        let mut info = sample_info();
+        info.total_size = Some(2000);
+        assert!(state.validate("https://example.com/file.bin", &info).is_err());
+        assert!(state.validate("https://example.com/other.bin", &sample_info()).is_err());
+    }
+
+    #[test]
+    fn test_part_completion() {
+        let state = sample_state();
+        assert!(state.parts[0].is_complete());
+        assert!(!state.parts[1].is_complete());
+        assert_eq!(state.parts[1].size(), 500);
+    }
+
+    #[test]
+    fn test_contiguous_progress_stops_at_first_gap() {
+        let mut state = sample_state();
+        state.parts[0].received = 100;
+        let tracker = StateTracker::new(state, None);
+        assert_eq!(tracker.contiguous(), 100);
+
+        tracker.record(0, 400).unwrap();
+        assert_eq!(tracker.contiguous(), 620);
+    }
+
+    #[test]
+    fn test_split_largest_part() {
+        let tracker = StateTracker::new(sample_state(), None);
+        assert_eq!(tracker.claim(1, 80), 80);
+
+        // Part 1 has 500 - 120 - 80 = 300 bytes left, split into 150 and 150.
+        assert_eq!(tracker.split_largest(100), Some((1, 2)));
+        assert_eq!(tracker.part(1), PartState { start: 500, end: 849, received: 120 });
+        assert_eq!(tracker.part(2), PartState { start: 850, end: 999, received: 0 });
+
+        tracker.record(1, 80).unwrap();
+        assert_eq!(tracker.claim(1, 500), 150);
+        tracker.record(1, 150).unwrap();
+        assert!(tracker.part(1).is_complete());
+        assert_eq!(tracker.claim(1, 10), 0);
+
+        assert_eq!(tracker.split_largest(100), None);
+        assert_eq!(tracker.split_largest(50), Some((2, 3)));
+        assert_eq!(tracker.contiguous(), 850);
+    }
+}
@@ -1,33 +1,202 @@
 //! # TCP Socket Handling
 //!
-//! This module provides utilities for establishing TCP connections.
+//! This module provides utilities for establishing TCP connections, including racing
+//! connection attempts to several addresses of a host ("Happy Eyeballs", RFC 8305).
+//! Every socket gets a connect timeout and read/write idle timeouts.
 use crate::error::DownloaderError;
+use std::io;
 use std::net::{TcpStream, IpAddr, SocketAddr};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// How long network operations may take before they fail with `DownloaderError::Timeout`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Longest time a single connection attempt may take.
+    pub connect: Duration,
+    /// Longest time a read or write may wait without making progress.
+    pub read: Duration,
+    /// Longest time a whole download may take, including retries; `None` for no limit.
+    /// It is checked whenever data arrives, so a stalled connection is noticed within
+    /// the `read` timeout.
+    pub download: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: Duration::from_secs(30),
+            read: Duration::from_secs(60),
+            download: None,
+        }
+    }
+}
+
+/// How long a connection attempt may run before the next address is tried in parallel,
+/// as recommended by RFC 8305.
+pub const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
 
 /// Establishes a TCP socket connection to the given IP address and port.
 ///
 /// # Parameters
 /// - `ip_address`: The IP address to connect to.
 /// - `port`: The port number.
+/// - `timeouts`: The connect timeout, and the idle timeout set on the socket.
 ///
 /// # Returns
 /// A `TcpStream` wrapped in a `Result`.
- 
-pub fn establish_tcp_socket(ip_address: IpAddr, port: u16) -> Result<TcpStream, DownloaderError> {
+///
+/// # Errors
+/// Returns `DownloaderError::Timeout` if the connection is not established in time, and
+/// `DownloaderError::ConnectionError` if it fails.
+pub fn establish_tcp_socket(ip_address: IpAddr, port: u16, timeouts: &Timeouts) -> Result<TcpStream, DownloaderError> {
     let socket_addr = SocketAddr::new(ip_address, port);
-    TcpStream::connect(socket_addr)
-        .map_err(|e| DownloaderError::ConnectionError(e.to_string()))
+    let stream = TcpStream::connect_timeout(&socket_addr, timeouts.connect).map_err(|e| {
+        if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock) {
+            DownloaderError::Timeout(format!(
+                "connecting to {} took longer than {:?}",
+                socket_addr, timeouts.connect
+            ))
+        } else {
+            DownloaderError::ConnectionError(e.to_string())
+        }
+    })?;
+    stream.set_read_timeout(Some(timeouts.read))?;
+    stream.set_write_timeout(Some(timeouts.read))?;
+    Ok(stream)
+}
+
+/// Connects to the first of `addresses` that accepts a connection on `port`.
+///
+/// Attempts are started in order, each one `CONNECTION_ATTEMPT_DELAY` after the previous
+/// one or as soon as it fails, and the first successful connection wins. Addresses should
+/// already be ordered, e.g. by `dns::interleave_families`.
+///
+/// # Parameters
+/// - `addresses`: The addresses to try, most preferred first.
+/// - `port`: The port number.
+/// - `timeouts`: The timeouts applied to each attempt and to the resulting socket.
+///
+/// # Returns
+/// The first established `TcpStream`.
+///
+/// # Errors
+/// Returns the error of the last failed attempt if no address accepts a connection.
+pub fn establish_tcp_socket_any(
+    addresses: &[IpAddr],
+    port: u16,
+    timeouts: &Timeouts,
+) -> Result<TcpStream, DownloaderError> {
+    if let [ip] = addresses {
+        return establish_tcp_socket(*ip, port, timeouts);
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let start = |ip: IpAddr| {
+        let sender = sender.clone();
+        let timeouts = *timeouts;
+        // A losing attempt's stream is dropped when the send fails after the race is over.
+        thread::spawn(move || {
+            let _ = sender.send(establish_tcp_socket(ip, port, &timeouts));
+        });
+    };
+
+    let mut remaining = addresses.iter().copied();
+    let mut in_flight = 0;
+    let mut last_error = DownloaderError::ConnectionError("No addresses to connect to".into());
+    loop {
+        if in_flight == 0 {
+            match remaining.next() {
+                Some(ip) => {
+                    start(ip);
+                    in_flight += 1;
+                }
+                None => return Err(last_error),
+            }
+        }
+
+        let result = if remaining.len() > 0 {
+            match receiver.recv_timeout(CONNECTION_ATTEMPT_DELAY) {
+                Ok(result) => result,
+                Err(_) => {
+                    // The current attempts are slow; start the next one alongside them.
+                    if let Some(ip) = remaining.next() {
+                        start(ip);
+                        in_flight += 1;
+                    }
+                    continue;
+                }
+            }
+        } else {
+            // `sender` is still alive here, so this only returns once an attempt finishes.
+            receiver.recv().expect("connection attempt sender dropped")
+        };
+
+        in_flight -= 1;
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::establish_tcp_socket;
-    use std::net::{IpAddr, Ipv4Addr};
+    use super::{establish_tcp_socket, establish_tcp_socket_any, Timeouts};
+    use crate::error::DownloaderError;
+    use std::io::Read;
+    use std::net::{IpAddr, Ipv4Addr, TcpListener};
+    use std::time::{Duration, Instant};
 
     #[test]
     fn test_valid_tcp_connection() {
         let ip = IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34));
-        let result = establish_tcp_socket(ip, 443);
+        let result = establish_tcp_socket(ip, 443, &Timeouts::default());
         assert!(result.is_ok(), "Expected a valid TCP connection, but got an error: {:?}", result.err());
     }
+
+    #[test]
+    fn test_race_skips_unreachable_addresses() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // 192.0.2.1 (TEST-NET-1) is never routed: the attempt either hangs or fails.
+        let addresses = [IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), IpAddr::V4(Ipv4Addr::LOCALHOST)];
+
+        let started = Instant::now();
+        let stream = establish_tcp_socket_any(&addresses, port, &Timeouts::default()).unwrap();
+        assert_eq!(stream.peer_addr().unwrap().ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_race_fails_when_every_address_fails() {
+        let port = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let addresses = [IpAddr::V4(Ipv4Addr::LOCALHOST), IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2))];
+        assert!(establish_tcp_socket_any(&addresses, port, &Timeouts::default()).is_err());
+        assert!(establish_tcp_socket_any(&[], port, &Timeouts::default()).is_err());
+    }
+
+    #[test]
+    fn test_connect_and_read_timeouts() {
+        let timeouts = Timeouts {
+            connect: Duration::from_millis(200),
+            read: Duration::from_millis(100),
+            download: None,
+        };
+        let result = establish_tcp_socket(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 80, &timeouts);
+        assert!(matches!(result, Err(DownloaderError::Timeout(_)) | Err(DownloaderError::ConnectionError(_))));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut stream = establish_tcp_socket(IpAddr::V4(Ipv4Addr::LOCALHOST), port, &timeouts).unwrap();
+        let _server_side = listener.accept().unwrap();
+        let started = Instant::now();
+        let error = DownloaderError::from(stream.read(&mut [0u8; 16]).unwrap_err());
+        assert!(matches!(error, DownloaderError::Timeout(_)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
 }
@@ -0,0 +1,240 @@
+//! # Temporary Files
+//!
+//! This module names and tracks the temporary files of a download. Part files are named
+//! after the output file plus an id of the download, so that concurrent downloads into
+//! the same directory never share them. Every temporary file that should not outlive the
+//! download is registered in a process-wide list, which an interrupt handler can clear.
+//!
+//! ## Features
+//! - Generates download ids that differ between downloads and processes.
+//! - Names part files `<output name>.<id>.part<N>` and the preallocated output
+//!   `<output name>.<id>.partial`, optionally in another directory.
+//! - Removes the temporary files of a download when it succeeds or fails.
+//! - Removes the temporary files of every running download on request, e.g. on Ctrl-C.
+
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::BuildHasher;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Temporary files of all running downloads, removed by `remove_registered`.
+static REGISTRY: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Distinguishes downloads started by this process in the same instant.
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Returns a new download id of 12 lowercase hex digits.
+///
+/// The id mixes the process id, the current time and a counter through a randomly
+/// seeded hasher, so ids of different downloads collide only by chance.
+pub fn new_download_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let hash = RandomState::new().hash_one((process::id(), nanos, count));
+    format!("{:012x}", hash >> 16)
+}
+
+/// Returns the path of a part file.
+///
+/// # Parameters
+/// - `output_file`: The final name of the output file.
+/// - `temp_dir`: Where part files are kept; `None` for the directory of the output file.
+/// - `id`: The id of the download.
+/// - `part_number`: The number of the part.
+///
+/// # Returns
+/// `<dir>/<output name>.<id>.part<N>`.
+pub fn part_path(output_file: &str, temp_dir: Option<&Path>, id: &str, part_number: usize) -> PathBuf {
+    temp_path(output_file, temp_dir, &format!("{}.part{}", id, part_number))
+}
+
+/// Returns the path of the preallocated file the output is written to until it is
+/// complete.
+///
+/// # Parameters
+/// - `output_file`: The final name of the output file.
+/// - `temp_dir`: Where the file is kept; `None` for the directory of the output file.
+/// - `id`: The id of the download.
+///
+/// # Returns
+/// `<dir>/<output name>.<id>.partial`.
+pub fn partial_path(output_file: &str, temp_dir: Option<&Path>, id: &str) -> PathBuf {
+    temp_path(output_file, temp_dir, &format!("{}.partial", id))
+}
+
+fn temp_path(output_file: &str, temp_dir: Option<&Path>, suffix: &str) -> PathBuf {
+    let output = Path::new(output_file);
+    let dir = match temp_dir {
+        Some(dir) => dir,
+        None => output.parent().unwrap_or_else(|| Path::new("")),
+    };
+    let name = output.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    dir.join(format!("{}.{}", name, suffix))
+}
+
+/// Returns whether any download, of this or another process, is writing a `.partial`
+/// file for `output_file`.
+pub fn partial_exists(output_file: &str, temp_dir: Option<&Path>) -> bool {
+    let dir = partial_path(output_file, temp_dir, "");
+    let (Some(dir), Some(name)) = (dir.parent(), Path::new(output_file).file_name()) else {
+        return false;
+    };
+    let prefix = format!("{}.", name.to_string_lossy());
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return false;
+    };
+    entries.flatten().any(|entry| {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        name.strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(".partial"))
+            .is_some_and(|id| id.len() == 12 && id.chars().all(|c| c.is_ascii_hexdigit()))
+    })
+}
+
+/// Removes every registered temporary file, ignoring files that are already gone.
+///
+/// Meant for an interrupt handler; downloads that are still running may fail afterwards.
+pub fn remove_registered() {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    for path in registry.drain(..) {
+        let _ = remove_if_exists(&path);
+    }
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// The temporary files of one download.
+///
+/// Files added to a set that is not kept are registered for `remove_registered`. Kept
+/// files, e.g. of a download that can be resumed, are left alone on failure and
+/// interrupt. Files are unregistered when the set is dropped.
+pub struct TempFiles {
+    paths: Mutex<Vec<PathBuf>>,
+    keep: bool,
+}
+
+impl TempFiles {
+    /// Creates an empty set. With `keep` set, files survive a failed download.
+    pub fn new(keep: bool) -> Self {
+        Self { paths: Mutex::new(Vec::new()), keep }
+    }
+
+    /// Adds `path` to the set and returns it.
+    pub fn add(&self, path: PathBuf) -> PathBuf {
+        if !self.keep {
+            REGISTRY.lock().unwrap().push(path.clone());
+        }
+        self.paths.lock().unwrap().push(path.clone());
+        path
+    }
+
+    /// Removes the files of the set after the download ended with `success`. Kept
+    /// files are only removed after a success.
+    pub fn clean_up(&self, success: bool) {
+        if success || !self.keep {
+            self.remove_all();
+        }
+    }
+
+    /// Removes every file of the set, ignoring files that are already gone.
+    pub fn remove_all(&self) {
+        for path in self.paths.lock().unwrap().iter() {
+            let _ = remove_if_exists(path);
+        }
+    }
+}
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        let paths = self.paths.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut registry = REGISTRY.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        registry.retain(|path| !paths.contains(path));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{new_download_id, part_path, partial_exists, partial_path, TempFiles, REGISTRY};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_download_ids_differ() {
+        let (a, b) = (new_download_id(), new_download_id());
+        assert_eq!(a.len(), 12);
+        assert!(a.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_part_path() {
+        assert_eq!(
+            part_path("downloads/image.jpg", None, "abc", 2),
+            PathBuf::from("downloads/image.jpg.abc.part2")
+        );
+        assert_eq!(part_path("image.jpg", None, "abc", 0), PathBuf::from("image.jpg.abc.part0"));
+        assert_eq!(
+            part_path("downloads/image.jpg", Some(Path::new("/tmp/parts")), "abc", 1),
+            PathBuf::from("/tmp/parts/image.jpg.abc.part1")
+        );
+    }
+
+    #[test]
+    fn test_partial_path() {
+        assert_eq!(partial_path("out/photo.jpg", None, "abc"), PathBuf::from("out/photo.jpg.abc.partial"));
+        assert_eq!(
+            partial_path("out/photo.jpg", Some(Path::new("/tmp/parts")), "abc"),
+            PathBuf::from("/tmp/parts/photo.jpg.abc.partial")
+        );
+
+        let dir = std::env::temp_dir().join(format!("pd-partial-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("photo.jpg").to_string_lossy().into_owned();
+        assert!(!partial_exists(&output, None));
+        fs::write(dir.join("photo.jpg.old.partial"), b"").unwrap();
+        assert!(!partial_exists(&output, None));
+        fs::write(partial_path(&output, None, &new_download_id()), b"").unwrap();
+        assert!(partial_exists(&output, None));
+        assert!(!partial_exists(&output, Some(&dir.join("elsewhere"))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_temp_files_are_registered_and_removed() {
+        let dir = std::env::temp_dir().join(format!("pd-temp-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.part0");
+        fs::write(&path, b"part").unwrap();
+
+        let kept = TempFiles::new(true);
+        kept.add(path.clone());
+        assert!(!REGISTRY.lock().unwrap().contains(&path));
+        kept.clean_up(false);
+        assert!(path.exists());
+        drop(kept);
+
+        let files = TempFiles::new(false);
+        files.add(path.clone());
+        assert!(REGISTRY.lock().unwrap().contains(&path));
+        files.clean_up(false);
+        assert!(!path.exists());
+        drop(files);
+        assert!(!REGISTRY.lock().unwrap().contains(&path));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,50 @@
+//! # Shared Helpers
+//!
+//! This module holds small helpers used by several other modules, which belong to none
+//! of them in particular.
+//!
+//! ## Features
+//! - Decodes `%XX` escapes in URLs and header parameters, as text or raw bytes.
+
+/// Decodes `%XX` escapes, e.g. in the userinfo of a URL.
+pub fn percent_decode(value: &str) -> String {
+    String::from_utf8_lossy(&percent_decode_bytes(value)).into_owned()
+}
+
+/// Decodes `%XX` escapes into raw bytes, for values that are not UTF-8.
+pub fn percent_decode_bytes(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).filter(|hex| hex.iter().all(u8::is_ascii_hexdigit));
+        let byte = hex.and_then(|hex| std::str::from_utf8(hex).ok()).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], byte) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{percent_decode, percent_decode_bytes};
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("p%40ss%20word"), "p@ss word");
+        assert_eq!(percent_decode("caf%C3%A9"), "caf\u{e9}");
+        // Malformed escapes are kept as they are.
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+        assert_eq!(percent_decode("%+1%-1"), "%+1%-1");
+        assert_eq!(percent_decode_bytes("%FF%fe"), vec![0xff, 0xfe]);
+    }
+}
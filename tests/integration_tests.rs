@@ -1,4 +1,4 @@
-use parallel_downloader::{DownloadConfig, DownloadManager, DownloaderError, dns, connection, http};
+use parallel_downloader::{DownloadConfig, DownloadManager, DownloaderError, dns, connection, http, tcp};
 use std::fs::{File, remove_file};
 use std::io::Read;
 
@@ -11,14 +11,22 @@ fn test_full_download_process() -> Result<(), DownloaderError> {
     let ip = dns::get_request_ip(HOSTNAME)?;
     assert!(ip.is_ipv4(), "Failed to resolve a valid IPv4 address for {}", HOSTNAME);
 
-    let mut stream = connection::establish_tls_connection(HOSTNAME, ip)?;
+    let mut stream = connection::establish_tls_connection(
+        HOSTNAME,
+        ip,
+        443,
+        &connection::TlsOptions::default(),
+        &tcp::Timeouts::default(),
+    )?;
     assert!(stream.get_ref().peer_addr().is_ok(), "Failed to establish a TLS connection to {}", HOSTNAME);
 
-    let (supports_range, total_size) = http::send_head_request(&mut stream, HOSTNAME, "/~perdisci/CSCI6760-F21/Project2-TestFiles/Uga-VII.jpg")?;
-    assert!(supports_range, "Server does not support range requests");
+    let url = url::Url::parse(URL)?;
+    let config = DownloadConfig::new(URL.to_string(), OUTPUT_FILE.to_string(), 4);
+    let info = http::send_head_request(&mut stream, &url, &config)?;
+    assert!(info.supports_range, "Server does not support range requests");
+    let total_size = info.total_size.expect("Server did not report the file size");
     assert!(total_size > 0, "Received invalid file size: {}", total_size);
 
-    let config = DownloadConfig::new(URL.to_string(), OUTPUT_FILE.to_string(), 4);
     let manager = DownloadManager::new(config, total_size);
 
     let result = manager.download();
@@ -31,21 +39,15 @@ fn test_full_download_process() -> Result<(), DownloaderError> {
 
     assert_eq!(buffer.len(), total_size as usize, "Downloaded file size does not match expected size");
 
-    cleanup_test_files(OUTPUT_FILE, 4, ".jpg");
+    // The downloader removes its own temporary files; only the output is left.
+    cleanup_test_files(OUTPUT_FILE);
 
     Ok(())
 }
 
-fn cleanup_test_files(output_file: &str, num_parts: usize, extension: &str) {
+fn cleanup_test_files(output_file: &str) {
     if let Err(e) = remove_file(output_file) {
         eprintln!("Failed to remove output file '{}': {}", output_file, e);
     }
-
-    for i in 0..num_parts {
-        let part_filename = format!("part{}{}", i, extension);
-        if let Err(e) = remove_file(&part_filename) {
-            eprintln!("Failed to remove part file '{}': {}", part_filename, e);
-        }
-    }
 }
 